@@ -5,7 +5,7 @@ use crate::data::LabeledData;
 use crate::data::DataSet;
 
 
-pub fn load_data(dataset_name: &str) -> Result<DataSet<f32>, std::io::Error> {
+pub fn load_data(dataset_name: &str) -> Result<DataSet<f32, u32>, std::io::Error> {
      let filename = format!("mnist/{}-labels-idx1-ubyte", dataset_name);
      let label_data = &FileData::new(&mut (File::open(filename))?)?;
      let filename = format!("mnist/{}-images-idx3-ubyte", dataset_name);
@@ -20,20 +20,17 @@ pub fn load_data(dataset_name: &str) -> Result<DataSet<f32>, std::io::Error> {
          images.push(image_data);
      }
 
-     let classifications: Vec<Vec<f32>> = label_data.data.clone().into_iter().map(|x| {
-        let mut vec: Vec<f32> = vec![0f32; 10];
-        vec[x as usize] = 1.0;
-        vec
-     }).collect();
+     //Keep labels as class indices, see DataSet::to_one_hot for dense targets
+     let classifications: Vec<u32> = label_data.data.clone().into_iter().map(|x| x as u32).collect();
      
-     let mut ret: Vec<LabeledData<f32>> = Vec::new();
+     let mut ret: Vec<LabeledData<f32, u32>> = Vec::new();
      for (image, classification) in images.into_iter().zip(classifications.into_iter()) {
-        ret.push(LabeledData::<f32> {
+        ret.push(LabeledData::<f32, u32> {
             data: image,
-            labels: classification,
+            labels: vec![classification],
         })
     }
-    Ok(DataSet::<f32> {
+    Ok(DataSet::<f32, u32> {
        data: ret
     })
 }
@@ -1,36 +1,51 @@
 pub mod mnist;
 
 use rand::prelude::*;
+use std::collections::HashMap;
 
+//Labels default to the same type as the data, sparse class labels use u32
 #[derive(Clone)]
-pub struct LabeledData<Data: Clone> {
+pub struct LabeledData<Data: Clone, Label: Clone = Data> {
     data: Vec<Data>,
-    labels: Vec<Data>,
+    labels: Vec<Label>,
 }
 
-impl<Data: Clone> LabeledData<Data> {
+impl<Data: Clone, Label: Clone> LabeledData<Data, Label> {
     fn get_data(&self) -> Vec<Data> {
         self.data.clone()
     }
 
-    fn get_labels(&self) -> Vec<Data> {
+    fn get_labels(&self) -> Vec<Label> {
         self.labels.clone()
     }
 }
 
-pub struct DataSet<Data: Clone> {
-    data: Vec<LabeledData<Data>>,
+pub struct DataSet<Data: Clone, Label: Clone = Data> {
+    data: Vec<LabeledData<Data, Label>>,
 }
 
-impl<Data: Clone> DataSet<Data> {
+impl<Data: Clone, Label: Clone> DataSet<Data, Label> {
     pub fn generate_epoc(&self, batch_size: usize) -> Vec<Self> {
-        let mut rng = rand::thread_rng();
-        let mut batch_data: Vec<LabeledData<Data>> = self.data.clone();
-        batch_data.shuffle(&mut rng);
+        self.generate_epoc_with_rng(batch_size, &mut rand::thread_rng())
+    }
+
+    //Same shuffle for the same rng state, used to replay an epoch when resuming
+    pub fn generate_epoc_with_rng<R: Rng>(&self, batch_size: usize, rng: &mut R) -> Vec<Self> {
+        let mut batch_data: Vec<LabeledData<Data, Label>> = self.data.clone();
+        batch_data.shuffle(rng);
 
         //Return
         batch_data.chunks(batch_size).map(|batch_data| {
-            DataSet::<Data> {
+            DataSet::<Data, Label> {
+                data: batch_data.to_vec(),
+            }
+        }).collect()
+    }
+
+    //Batches in order without shuffling, the last may be smaller
+    pub fn batches(&self, batch_size: usize) -> Vec<Self> {
+        self.data.chunks(batch_size).map(|batch_data| {
+            DataSet::<Data, Label> {
                 data: batch_data.to_vec(),
             }
         }).collect()
@@ -38,11 +53,11 @@ impl<Data: Clone> DataSet<Data> {
 
     pub fn generate_batch(&self, batch_size: usize) -> Self {
         let mut rng = rand::thread_rng();
-        let mut batch_data: Vec<LabeledData<Data>> = self.data[..].choose_multiple(&mut rng, batch_size).cloned().collect();
+        let mut batch_data: Vec<LabeledData<Data, Label>> = self.data[..].choose_multiple(&mut rng, batch_size).cloned().collect();
         batch_data.shuffle(&mut rng);
 
         //Return
-        DataSet::<Data> {
+        DataSet::<Data, Label> {
             data: batch_data,
         }
     }
@@ -52,8 +67,8 @@ impl<Data: Clone> DataSet<Data> {
         batch_data
     }
     
-    pub fn get_labels(&self) -> Vec<Data> {
-        let batch_labels: Vec<Data> = self.data.iter().map(|item| item.clone().get_labels().into_iter()).flatten().collect();
+    pub fn get_labels(&self) -> Vec<Label> {
+        let batch_labels: Vec<Label> = self.data.iter().flat_map(|item| item.clone().get_labels().into_iter()).collect();
         batch_labels
     }
 
@@ -61,3 +76,54 @@ impl<Data: Clone> DataSet<Data> {
         self.data.len()
     }
 }
+
+impl<Data: Clone> DataSet<Data, u32> {
+    //Expand class indices into one-hot vectors for costs that take dense targets
+    pub fn to_one_hot(&self, class_count: usize) -> DataSet<Data, f32> {
+        let data = self.data.iter().map(|item| {
+            let labels = item.labels.iter().flat_map(|&class| {
+                assert!((class as usize) < class_count, "Class index {} out of range for {} classes", class, class_count);
+                let mut vec: Vec<f32> = vec![0f32; class_count];
+                vec[class as usize] = 1.0;
+                vec.into_iter()
+            }).collect();
+            LabeledData::<Data, f32> {
+                data: item.data.clone(),
+                labels,
+            }
+        }).collect();
+
+        //Return
+        DataSet::<Data, f32> {
+            data,
+        }
+    }
+
+    //Batch of classes_per_batch classes with items_per_class items each, so every item has positives and negatives in the batch
+    //Classes with too few items are sampled with replacement
+    pub fn generate_class_batch(&self, classes_per_batch: usize, items_per_class: usize) -> Self {
+        let mut rng = rand::thread_rng();
+        let mut classes: HashMap<u32, Vec<&LabeledData<Data, u32>>> = HashMap::new();
+        for item in &self.data {
+            assert_eq!(item.labels.len(), 1, "Expected a single class index per item");
+            classes.entry(item.labels[0]).or_default().push(item);
+        }
+        assert!(classes.len() >= classes_per_batch, "Only {} classes to pick {} from", classes.len(), classes_per_batch);
+
+        let class_keys: Vec<u32> = classes.keys().cloned().collect();
+        let mut batch_data: Vec<LabeledData<Data, u32>> = Vec::with_capacity(classes_per_batch * items_per_class);
+        for class in class_keys.choose_multiple(&mut rng, classes_per_batch) {
+            let items = &classes[class];
+            if items.len() >= items_per_class {
+                batch_data.extend(items.choose_multiple(&mut rng, items_per_class).map(|&item| item.clone()));
+            } else {
+                batch_data.extend((0..items_per_class).map(|_| (*items.choose(&mut rng).unwrap()).clone()));
+            }
+        }
+
+        //Return
+        DataSet::<Data, u32> {
+            data: batch_data,
+        }
+    }
+}
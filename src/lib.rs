@@ -0,0 +1,5 @@
+pub mod data;
+pub mod pipelines;
+pub mod network;
+pub mod optimisers;
+pub mod trainer;
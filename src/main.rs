@@ -1,7 +1,4 @@
-mod data;
-mod pipelines;
-mod network;
-mod optimisers;
+use rust_perceptron::{data, pipelines, network, optimisers, trainer};
 
 use futures::executor::block_on;
 
@@ -23,54 +20,45 @@ fn main() {
     use network::LayerType::*;
     use network::CostFunction::*;
     let generator_topology = vec![FullyConnected(128), Relu, FullyConnected(output_size), Softmax];
-    let mut my_network = network::perceptron::Network::new(28*28, generator_topology, CrossEntropy);
-    //let mut my_network = network::perceptron::Network::load_from_file("weights/network.bin");
+    let my_network = network::perceptron::Network::new(28*28, generator_topology, SparseCrossEntropy(None, 0.0));
+    //let my_network = network::perceptron::Network::load_from_file("weights/network.bin");
 
-    let mut optimiser = optimisers::Stochasticgradientdescent::new(0.001);
-
-    let network_topology = my_network.get_topology();
+    let optimiser = optimisers::Stochasticgradientdescent::new(0.001);
 
     //Connect to device
     let anchor = block_on(pipelines::Device::new());
 
-    //Load network data to gpu
-    let mut network_data = my_network.load_to_gpu(&anchor);
-
     //Run training loop
-    for i in 0..1 {
-        let mut j = 0;
-        //Break epoc into batches
-        for batch in training_data.generate_epoc(batch_size).into_iter() {
-            println!("Epoc: {}, Batch: {}", i, j);
-            j += 1;
-            let batch_images = batch.get_data();
-            let batch_labels = batch.get_labels();
-            
-            //Step optimization
-            let network_grads = my_network.backprop::<f32>(&batch_images, &batch_labels, &mut network_data, &anchor, batch.get_size());
-            optimiser.step(&mut network_data, &network_grads, &anchor, &network_topology);
-
-            //Get test batch
-            let test_batch = test_data.generate_batch(batch_size);
-            let batch_images = test_batch.get_data();
-            let batch_labels = test_batch.get_labels();
-
-            //Compute cost
-            let prediction = my_network.feedforward::<f32>(&batch_images, &network_data, &anchor, batch_size);
-            let cost = my_network.cost::<f32>(&prediction, &batch_labels, &anchor, batch_size, true);
-            println!("Cost: {:?}", from_gpu::<f32>(&cost, &anchor, 1).unwrap());
-        
-            //Show sample prediction with ground truth for it
-            println!("{:?}", from_gpu::<f32>(&prediction, &anchor, output_size).unwrap());
-            println!("{:?}", batch_labels.get(0..output_size));
-
-        }
-        //End epoc
-    }
+    use trainer::callbacks::*;
+    let config = trainer::TrainerConfig {
+        epochs: 10,
+        batch_size,
+        validation_frequency: 1,
+        top_k: 3,
+        seed: 0,
+        checkpoint: Some(trainer::CheckpointConfig {
+            directory: "weights/checkpoints".to_string(),
+            epoch_frequency: 1,
+            batch_frequency: 500,
+            keep_last: 3,
+        }),
+    };
+    let mut callbacks: Vec<Box<dyn trainer::Callback<f32, u32>>> = vec![
+        Box::new(Logger { batch_frequency: 100 }),
+        Box::new(LearningRateScheduler { initial_learning_rate: 0.001, schedule: Schedule::Step(5, 0.5) }),
+        Box::new(EarlyStopping::new(Monitor::Cost, 3, 0.001, BestWeights::Memory)),
+        Box::new(Checkpoint { filelocation: "weights/network.bin".to_string(), epoch_frequency: 1 }),
+    ];
+    //Continue an interrupted run if there is a checkpoint
+    let mut my_trainer = match trainer::checkpoint::latest_checkpoint("weights/checkpoints") {
+        Some(filelocation) => trainer::Trainer::resume(&filelocation, training_data, Some(test_data), config, anchor, &mut callbacks),
+        None => trainer::Trainer::new(my_network, optimiser, training_data, Some(test_data), config, anchor),
+    };
+    my_trainer.fit(&mut callbacks);
 
     //Save network
-    my_network.save_from_gpu(&anchor, &network_data);
-    my_network.save_to_file("weights/network.bin");
+    my_trainer.save_from_gpu();
+    my_trainer.network.save_to_file("weights/network.bin");
 }
 
 //fn to_gpu<T: bytemuck::Pod>(input: &Vec<T>, anchor: &pipelines::Device) -> wgpu::Buffer {
@@ -89,64 +77,3 @@ fn main() {
 //    //Return
 //    input_buffer
 //}
-
-fn from_gpu<T: bytemuck::Pod>(buffer: &wgpu::Buffer, anchor: &pipelines::Device, size: usize) -> Option<Vec<T>> {
-    let queue = &anchor.queue;
-    let device = &anchor.device;
-    let type_size = std::mem::size_of::<T>();
-
-    //Create command buffer encoder
-    let mut encoder = device.create_command_encoder(
-        &wgpu::CommandEncoderDescriptor {
-            label: None,
-        }
-    );
-
-    //Copy to readable buffer
-    let staging_buffer = device.create_buffer(
-        &wgpu::BufferDescriptor {
-            label: Some("Staging buffer"),
-            size: (type_size * size) as wgpu::BufferAddress,
-            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        }
-    );
-    encoder.copy_buffer_to_buffer(
-        buffer, 0,
-        &staging_buffer, 0,
-        (type_size * size) as wgpu::BufferAddress,
-    );
-
-    //Submit commands to gpu
-    queue.submit(Some(encoder.finish()));
-
-    //Create future of the computation
-    let buffer_slice = staging_buffer.slice(..);
-    let buffer_future = buffer_slice.map_async(wgpu::MapMode::Read);
-        
-    //Register mapping callbacks
-    device.poll(wgpu::Maintain::Wait);
-
-    //Wait for computation to complete
-    block_on(async {
-        match buffer_future.await {
-            Ok(()) => {
-                //Get buffer contents
-                let data = buffer_slice.get_mapped_range();
-                //Convert to T
-                let result: Vec<T> = data.chunks_exact(type_size).map(|b| *bytemuck::from_bytes::<T>(b)).collect();
-                //Drop mapped view
-                drop(data);
-                //Unmap buffer
-                staging_buffer.unmap();
-
-                //Return
-                return Some(result)
-            }
-            Err(e) => {
-                eprintln!("Error: {}", e);
-                None
-            }
-        }
-    })
-}
@@ -6,6 +6,11 @@ use wgpu::util::{BufferInitDescriptor, DeviceExt};
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CrossEntropy {
     pub dimension: usize,
+    //Default to unweighted and unsmoothed for files saved before these existed
+    #[serde(default)]
+    pub class_weight: Vec<f32>,
+    #[serde(default)]
+    pub smoothing: f32,
 }
 
 #[typetag::serde]
@@ -18,6 +23,23 @@ impl super::CostFunction for CrossEntropy {
             batch_size: usize,) -> wgpu::Buffer {
         let device = &anchor.device;
 
+        //Load data to gpu
+        let weight_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&super::uploaded_class_weights(&self.class_weight, self.dimension)[..]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        let smoothing_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&[self.smoothing]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
         //Create error pipeline
         let error_uniforms = {
             let uniform_data = [self.dimension as u32, batch_size as u32];
@@ -34,6 +56,8 @@ impl super::CostFunction for CrossEntropy {
                 &error_uniforms,
                 prediction,
                 target,
+                &weight_buffer,
+                &smoothing_buffer,
             ),
             self.dimension,
             batch_size,
@@ -54,6 +78,23 @@ impl super::CostFunction for CrossEntropy {
                   batch_size: usize,) -> wgpu::Buffer {
         let device = &anchor.device;
 
+        //Load data to gpu
+        let weight_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&super::uploaded_class_weights(&self.class_weight, self.dimension)[..]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        let smoothing_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&[self.smoothing]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
         //Create loss pipeline
         let loss_uniforms = {
             let uniform_data = [self.dimension as u32, batch_size as u32];
@@ -70,6 +111,8 @@ impl super::CostFunction for CrossEntropy {
                 &loss_uniforms,
                 prediction,
                 target,
+                &weight_buffer,
+                &smoothing_buffer,
             ),
             self.dimension,
             batch_size,
@@ -0,0 +1,106 @@
+use crate::pipelines;
+
+use serde::{Serialize, Deserialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+//Squared error for residuals within delta and absolute error beyond it
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Huber {
+    pub dimension: usize,
+    pub delta: f32,
+}
+
+#[typetag::serde]
+impl super::CostFunction for Huber {
+    fn cost(&self,
+            prediction: &wgpu::Buffer,
+            target: &wgpu::Buffer,
+            anchor: &pipelines::Device,
+            encoder: &mut wgpu::CommandEncoder,
+            batch_size: usize,) -> wgpu::Buffer {
+        let device = &anchor.device;
+
+        //Load data to gpu
+        let delta_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&[self.delta]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create error pipeline
+        let error_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let error_pipeline = pipelines::huber::Pipeline::new::<f32>(anchor, (
+                &error_uniforms,
+                prediction,
+                target,
+                &delta_buffer,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run error pipeline
+        error_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Return
+        error_pipeline.output_buffer
+    }
+
+    fn cost_prime(&self,
+                  prediction: &wgpu::Buffer,
+                  target: &wgpu::Buffer,
+                  anchor: &pipelines::Device,
+                  encoder: &mut wgpu::CommandEncoder,
+                  batch_size: usize,) -> wgpu::Buffer {
+        let device = &anchor.device;
+
+        //Load data to gpu
+        let delta_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&[self.delta]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create loss pipeline
+        let loss_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let loss_pipeline = pipelines::huberprime::Pipeline::new::<f32>(anchor, (
+                &loss_uniforms,
+                prediction,
+                target,
+                &delta_buffer,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run loss pipeline
+        loss_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Return
+        loss_pipeline.output_buffer
+    }
+}
@@ -2,6 +2,20 @@ use crate::pipelines;
 
 pub mod squarederror;
 pub mod crossentropy;
+pub mod softmaxcrossentropy;
+pub mod sparsecrossentropy;
+pub mod sparsesoftmaxcrossentropy;
+pub mod binarycrossentropy;
+pub mod binarycrossentropylogits;
+pub mod huber;
+pub mod meanabsoluteerror;
+pub mod logcosh;
+pub mod quantile;
+pub mod kldivergence;
+pub mod focal;
+pub mod softmaxfocal;
+pub mod contrastive;
+pub mod tripletmargin;
 
 #[typetag::serde(tag = "type")]
 pub trait CostFunction {
@@ -20,6 +34,16 @@ pub trait CostFunction {
                   anchor: &pipelines::Device,
                   encoder: &mut wgpu::CommandEncoder,
                   batch_size: usize,) -> wgpu::Buffer;
+
+    //Number of trailing layers folded into the cost
+    fn fused_layers(&self) -> usize {
+        0
+    }
+
+    //True when the network output is logits the cost squashes itself rather than probabilities
+    fn expects_logits(&self) -> bool {
+        false
+    }
 }
 
 pub fn generate_cost(input_size: usize, cost_function: super::CostFunction) -> Box<dyn CostFunction> {
@@ -30,10 +54,291 @@ pub fn generate_cost(input_size: usize, cost_function: super::CostFunction) -> B
                 dimension: input_size,
             })
         },
-        CrossEntropy => {
+        CrossEntropy(class_weight, smoothing) => {
             Box::new(crossentropy::CrossEntropy {
                 dimension: input_size,
+                class_weight: class_weights(input_size, class_weight),
+                smoothing: label_smoothing(smoothing),
+            })
+        },
+        SparseCrossEntropy(class_weight, smoothing) => {
+            Box::new(sparsecrossentropy::SparseCrossEntropy {
+                dimension: input_size,
+                class_weight: class_weights(input_size, class_weight),
+                smoothing: label_smoothing(smoothing),
+            })
+        },
+        BinaryCrossEntropy(pos_weight) => {
+            Box::new(binarycrossentropy::BinaryCrossEntropy {
+                dimension: input_size,
+                pos_weight: class_weights(input_size, pos_weight),
+            })
+        },
+        BinaryCrossEntropyWithLogits(pos_weight) => {
+            Box::new(binarycrossentropylogits::BinaryCrossEntropyWithLogits {
+                dimension: input_size,
+                pos_weight: class_weights(input_size, pos_weight),
+            })
+        },
+        Huber(delta) => {
+            assert!(delta > 0.0, "Huber delta must be positive");
+            Box::new(huber::Huber {
+                dimension: input_size,
+                delta,
+            })
+        },
+        MeanAbsoluteError => {
+            Box::new(meanabsoluteerror::MeanAbsoluteError {
+                dimension: input_size,
+            })
+        },
+        LogCosh => {
+            Box::new(logcosh::LogCosh {
+                dimension: input_size,
+            })
+        },
+        KlDivergence(temperature) => {
+            assert!(temperature > 0.0, "Temperature must be positive");
+            Box::new(kldivergence::KlDivergence {
+                dimension: input_size,
+                temperature,
+                fused_layers: 0,
+            })
+        },
+        Focal(gamma, alpha) => {
+            assert!(gamma >= 0.0, "Focal gamma must not be negative");
+            Box::new(focal::Focal {
+                dimension: input_size,
+                gamma,
+                alpha,
+            })
+        },
+        Contrastive(margin) => {
+            assert!(margin > 0.0, "Margin must be positive");
+            Box::new(contrastive::Contrastive {
+                dimension: input_size,
+                margin,
+            })
+        },
+        TripletMargin(margin) => {
+            assert!(margin > 0.0, "Margin must be positive");
+            Box::new(tripletmargin::TripletMargin {
+                dimension: input_size,
+                margin,
+            })
+        },
+        Quantile(tau) => {
+            assert!(tau > 0.0 && tau < 1.0, "Quantile must be between 0 and 1");
+            Box::new(quantile::Quantile {
+                dimension: input_size,
+                tau,
+            })
+        },
+    }
+}
+
+fn label_smoothing(smoothing: f32) -> f32 {
+    assert!((0.0..1.0).contains(&smoothing), "Label smoothing must be in [0, 1)");
+    smoothing
+}
+
+//Files written before class weights existed deserialize with none, which means unweighted
+fn uploaded_class_weights(class_weight: &[f32], dimension: usize) -> Vec<f32> {
+    match class_weight.is_empty() {
+        true => vec![1.0; dimension],
+        false => class_weight.to_vec(),
+    }
+}
+
+//Unweighted classes get a weight of one
+fn class_weights(input_size: usize, weights: Option<Vec<f32>>) -> Vec<f32> {
+    match weights {
+        Some(weights) => {
+            assert_eq!(weights.len(), input_size, "Expected one weight per class");
+            weights
+        },
+        None => vec![1.0; input_size],
+    }
+}
+
+//Used when the topology ends in a softmax layer
+pub fn generate_softmax_cost(input_size: usize, cost_function: super::CostFunction) -> Box<dyn CostFunction> {
+    use super::CostFunction::*;
+    match cost_function {
+        CrossEntropy(class_weight, smoothing) => {
+            Box::new(softmaxcrossentropy::SoftmaxCrossEntropy {
+                dimension: input_size,
+                class_weight: class_weights(input_size, class_weight),
+                smoothing: label_smoothing(smoothing),
+            })
+        },
+        SparseCrossEntropy(class_weight, smoothing) => {
+            Box::new(sparsesoftmaxcrossentropy::SparseSoftmaxCrossEntropy {
+                dimension: input_size,
+                class_weight: class_weights(input_size, class_weight),
+                smoothing: label_smoothing(smoothing),
+            })
+        },
+        KlDivergence(temperature) => {
+            assert!(temperature > 0.0, "Temperature must be positive");
+            Box::new(kldivergence::KlDivergence {
+                dimension: input_size,
+                temperature,
+                fused_layers: 1,
+            })
+        },
+        Focal(gamma, alpha) => {
+            assert!(gamma >= 0.0, "Focal gamma must not be negative");
+            Box::new(softmaxfocal::SoftmaxFocal {
+                dimension: input_size,
+                gamma,
+                alpha,
             })
         },
+        other => generate_cost(input_size, other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipelines;
+    use crate::network::metrics::read_buffer;
+
+    use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+    //Textbook definitions of the regression costs, per element of the residual prediction - ground
+    //They are written from the definitions rather than the kernels so the gpu test has something independent to compare against
+    fn huber(residual: f64, delta: f64) -> f64 {
+        match residual.abs() <= delta {
+            true => 0.5 * residual * residual,
+            false => delta * (residual.abs() - 0.5 * delta),
+        }
+    }
+
+    fn absolute_error(residual: f64) -> f64 {
+        residual.abs()
+    }
+
+    fn log_cosh(residual: f64) -> f64 {
+        residual.cosh().ln()
+    }
+
+    fn quantile(residual: f64, tau: f64) -> f64 {
+        //Pinball loss of the target y against the prediction p is max(tau * (y - p), (tau - 1) * (y - p))
+        (tau * -residual).max((tau - 1.0) * -residual)
+    }
+
+    fn reference_cost(cost: &super::super::CostFunction, residual: f64) -> f64 {
+        use super::super::CostFunction::*;
+        match cost {
+            Huber(delta) => huber(residual, *delta as f64),
+            MeanAbsoluteError => absolute_error(residual),
+            LogCosh => log_cosh(residual),
+            Quantile(tau) => quantile(residual, *tau as f64),
+            _ => unreachable!(),
+        }
+    }
+
+    //Derivatives of the definitions with respect to the prediction
+    fn reference_prime(cost: &super::super::CostFunction, residual: f64) -> f64 {
+        use super::super::CostFunction::*;
+        match cost {
+            Huber(delta) => {
+                let delta = *delta as f64;
+                match residual.abs() <= delta {
+                    true => residual,
+                    false => delta * residual.signum(),
+                }
+            },
+            MeanAbsoluteError => residual.signum(),
+            LogCosh => residual.sinh() / residual.cosh(),
+            Quantile(tau) => {
+                let tau = *tau as f64;
+                match residual > 0.0 {
+                    true => 1.0 - tau,
+                    false => -tau,
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn regression_costs() -> Vec<super::super::CostFunction> {
+        use super::super::CostFunction::*;
+        vec![Huber(1.0), Huber(0.25), MeanAbsoluteError, LogCosh, Quantile(0.1), Quantile(0.5), Quantile(0.9)]
+    }
+
+    //Residuals away from the kinks at 0 and +-delta so the finite differences are smooth,
+    //the outer ones are large enough that cosh overflows f32
+    const RESIDUALS: [f64; 10] = [-100.0, -3.7, -1.2, -0.6, -0.1, 0.05, 0.4, 0.9, 2.3, 100.0];
+
+    #[test]
+    fn reference_primes_match_finite_differences() {
+        let h = 0.000001;
+        for cost in regression_costs().iter() {
+            for &residual in RESIDUALS.iter() {
+                let numeric = (reference_cost(cost, residual + h) - reference_cost(cost, residual - h)) / (2.0 * h);
+                let reference = reference_prime(cost, residual);
+                assert!((reference - numeric).abs() < 0.00001, "{:?} at {}: reference prime {} but numeric {}", cost, residual, reference, numeric);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Huber delta must be positive")]
+    fn huber_rejects_non_positive_delta() {
+        super::generate_cost(4, super::super::CostFunction::Huber(0.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Label smoothing must be in [0, 1)")]
+    fn cross_entropy_rejects_full_smoothing() {
+        super::generate_softmax_cost(4, super::super::CostFunction::CrossEntropy(None, 1.0));
+    }
+
+    fn storage_buffer(anchor: &pipelines::Device, data: &[f32]) -> wgpu::Buffer {
+        anchor.device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(data),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        )
+    }
+
+    //The tests above only check the references, the kernels themselves are only covered here
+    //Cost sums over features and prime averages over the batch
+    #[test]
+    #[ignore = "needs a gpu adapter"]
+    fn kernels_match_cpu_references() {
+        let anchor = futures::executor::block_on(pipelines::Device::new());
+        let (dimension, batch_size) = (RESIDUALS.len() / 2, 2);
+        let ground: Vec<f32> = (0..dimension * batch_size).map(|index| index as f32 * 0.3 - 1.0).collect();
+        let prediction: Vec<f32> = ground.iter().zip(RESIDUALS.iter()).map(|(ground, residual)| ground + *residual as f32).collect();
+        let prediction_buffer = storage_buffer(&anchor, &prediction);
+        let ground_buffer = storage_buffer(&anchor, &ground);
+
+        for cost_function in regression_costs().into_iter() {
+            let residuals: Vec<f64> = prediction.iter().zip(ground.iter()).map(|(prediction, ground)| (prediction - ground) as f64).collect();
+            let name = format!("{:?}", cost_function);
+            let reference: Vec<f64> = residuals.iter().map(|&residual| reference_cost(&cost_function, residual)).collect();
+            let reference_prime: Vec<f64> = residuals.iter().map(|&residual| reference_prime(&cost_function, residual) / batch_size as f64).collect();
+
+            let cost = super::generate_cost(dimension, cost_function);
+            let mut encoder = anchor.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            let cost_buffer = cost.cost(&prediction_buffer, &ground_buffer, &anchor, &mut encoder, batch_size);
+            let prime_buffer = cost.cost_prime(&prediction_buffer, &ground_buffer, &anchor, &mut encoder, batch_size);
+            anchor.queue.submit(Some(encoder.finish()));
+
+            let costs = read_buffer(&cost_buffer, &anchor, batch_size).unwrap();
+            for (column, &value) in costs.iter().enumerate() {
+                let expected: f64 = reference[column * dimension..(column + 1) * dimension].iter().sum();
+                assert!((value as f64 - expected).abs() < 0.0001 * expected.abs().max(1.0), "{} cost of item {}: {} but expected {}", name, column, value, expected);
+            }
+            let primes = read_buffer(&prime_buffer, &anchor, dimension * batch_size).unwrap();
+            for (index, &value) in primes.iter().enumerate() {
+                assert!((value as f64 - reference_prime[index]).abs() < 0.0001, "{} prime {}: {} but expected {}", name, index, value, reference_prime[index]);
+            }
+        }
     }
 }
@@ -0,0 +1,148 @@
+use crate::pipelines;
+
+use serde::{Serialize, Deserialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+//Focal loss on the softmax of the prediction logits
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SoftmaxFocal {
+    pub dimension: usize,
+    pub gamma: f32,
+    pub alpha: f32,
+}
+
+#[typetag::serde]
+impl super::CostFunction for SoftmaxFocal {
+    fn fused_layers(&self) -> usize {
+        //Takes logits, so the trailing softmax layer is skipped
+        1
+    }
+
+    fn cost(&self,
+            prediction: &wgpu::Buffer,
+            target: &wgpu::Buffer,
+            anchor: &pipelines::Device,
+            encoder: &mut wgpu::CommandEncoder,
+            batch_size: usize,) -> wgpu::Buffer {
+        let device = &anchor.device;
+
+        //Load data to gpu
+        let parameter_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&[self.gamma, self.alpha]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create error pipeline
+        let error_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        //Reduce each column to its log sum exp once
+        let log_sum_exp_pipeline = pipelines::logsumexp::Pipeline::new::<f32>(anchor, (
+                &error_uniforms,
+                prediction,
+            ),
+            self.dimension,
+            batch_size,
+        );
+        log_sum_exp_pipeline.run(encoder, self.dimension, batch_size);
+
+        let error_pipeline = pipelines::softmaxfocal::Pipeline::new::<f32>(anchor, (
+                &error_uniforms,
+                prediction,
+                target,
+                &parameter_buffer,
+                &log_sum_exp_pipeline.output_buffer,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run error pipeline
+        error_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Return
+        error_pipeline.output_buffer
+    }
+
+    fn cost_prime(&self,
+                  prediction: &wgpu::Buffer,
+                  target: &wgpu::Buffer,
+                  anchor: &pipelines::Device,
+                  encoder: &mut wgpu::CommandEncoder,
+                  batch_size: usize,) -> wgpu::Buffer {
+        let device = &anchor.device;
+
+        //Load data to gpu
+        let parameter_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&[self.gamma, self.alpha]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create loss pipeline
+        let loss_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        //Reduce each column to its log sum exp once
+        let log_sum_exp_pipeline = pipelines::logsumexp::Pipeline::new::<f32>(anchor, (
+                &loss_uniforms,
+                prediction,
+            ),
+            self.dimension,
+            batch_size,
+        );
+        log_sum_exp_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Sum the scaled gradients of each column once
+        let total_pipeline = pipelines::softmaxfocaltotal::Pipeline::new::<f32>(anchor, (
+                &loss_uniforms,
+                prediction,
+                target,
+                &parameter_buffer,
+                &log_sum_exp_pipeline.output_buffer,
+            ),
+            self.dimension,
+            batch_size,
+        );
+        total_pipeline.run(encoder, self.dimension, batch_size);
+
+        let loss_pipeline = pipelines::softmaxfocalprime::Pipeline::new::<f32>(anchor, (
+                &loss_uniforms,
+                prediction,
+                target,
+                &parameter_buffer,
+                &log_sum_exp_pipeline.output_buffer,
+                &total_pipeline.output_buffer,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run loss pipeline
+        loss_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Return
+        loss_pipeline.output_buffer
+    }
+}
@@ -0,0 +1,134 @@
+use crate::pipelines;
+
+use serde::{Serialize, Deserialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+//Triplet margin loss between each item and its hardest positive and negative in the batch
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TripletMargin {
+    pub dimension: usize,
+    pub margin: f32,
+}
+
+impl TripletMargin {
+    //Target is n class indices, returns the hardest positive and negative of each item
+    fn mine(&self,
+            prediction: &wgpu::Buffer,
+            target: &wgpu::Buffer,
+            uniforms: &wgpu::Buffer,
+            anchor: &pipelines::Device,
+            encoder: &mut wgpu::CommandEncoder,
+            batch_size: usize,) -> wgpu::Buffer {
+        //Create mining pipeline
+        let mining_pipeline = pipelines::tripletmining::Pipeline::new::<f32>(anchor, (
+                uniforms,
+                prediction,
+                target,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run mining pipeline
+        mining_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Return
+        mining_pipeline.output_buffer
+    }
+}
+
+#[typetag::serde]
+impl super::CostFunction for TripletMargin {
+    fn cost(&self,
+            prediction: &wgpu::Buffer,
+            target: &wgpu::Buffer,
+            anchor: &pipelines::Device,
+            encoder: &mut wgpu::CommandEncoder,
+            batch_size: usize,) -> wgpu::Buffer {
+        let device = &anchor.device;
+
+        //Load data to gpu
+        let margin_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&[self.margin]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create error pipeline
+        let error_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let mined = self.mine(prediction, target, &error_uniforms, anchor, encoder, batch_size);
+        let error_pipeline = pipelines::tripletmargin::Pipeline::new::<f32>(anchor, (
+                &error_uniforms,
+                &mined,
+                &margin_buffer,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run error pipeline
+        error_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Return
+        error_pipeline.output_buffer
+    }
+
+    fn cost_prime(&self,
+                  prediction: &wgpu::Buffer,
+                  target: &wgpu::Buffer,
+                  anchor: &pipelines::Device,
+                  encoder: &mut wgpu::CommandEncoder,
+                  batch_size: usize,) -> wgpu::Buffer {
+        let device = &anchor.device;
+
+        //Load data to gpu
+        let margin_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&[self.margin]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create loss pipeline
+        let loss_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let mined = self.mine(prediction, target, &loss_uniforms, anchor, encoder, batch_size);
+        let loss_pipeline = pipelines::tripletmarginprime::Pipeline::new::<f32>(anchor, (
+                &loss_uniforms,
+                prediction,
+                &mined,
+                &margin_buffer,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run loss pipeline
+        loss_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Return
+        loss_pipeline.output_buffer
+    }
+}
@@ -0,0 +1,333 @@
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::{self, DeserializeOwned, MapAccess, Visitor};
+use std::fmt;
+
+use super::layers;
+use super::cost;
+
+//Model files are a bincode header followed by the bincode payload
+//Files written before the header existed are read as a network of format version 0
+pub const MAGIC: [u8; 4] = *b"RPNN";
+
+//What the payload holds, each kind has its own format version and migrations
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadKind {
+    Network,
+    GraphNetwork,
+    MultiHeadNetwork,
+    TrainingCheckpoint,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Header {
+    pub magic: [u8; 4],
+    pub kind: PayloadKind,
+    //Format version of this kind of payload
+    pub format_version: u32,
+    //Version of the crate that wrote the file
+    pub crate_version: String,
+    pub payload_size: u64,
+    //FNV-1a of the payload
+    pub checksum: u64,
+}
+
+//Upgrades a payload from format version i to i + 1, where i is its index in MIGRATIONS
+pub type Migration = fn(Vec<u8>) -> Vec<u8>;
+
+//Types saved with a header
+//Changing the serialized layout of a type means bumping its FORMAT_VERSION and appending a migration to its MIGRATIONS,
+//layers are shared, so changing one bumps every kind
+pub trait Payload: Serialize + DeserializeOwned {
+    const KIND: PayloadKind;
+    const FORMAT_VERSION: u32;
+    const MIGRATIONS: &'static [Migration];
+}
+
+impl Payload for super::perceptron::Network {
+    const KIND: PayloadKind = PayloadKind::Network;
+    const FORMAT_VERSION: u32 = 1;
+    const MIGRATIONS: &'static [Migration] = &[
+        migrate_headerless,
+    ];
+}
+
+impl Payload for super::graph::GraphNetwork {
+    const KIND: PayloadKind = PayloadKind::GraphNetwork;
+    const FORMAT_VERSION: u32 = 0;
+    const MIGRATIONS: &'static [Migration] = &[];
+}
+
+impl Payload for super::multihead::MultiHeadNetwork {
+    const KIND: PayloadKind = PayloadKind::MultiHeadNetwork;
+    const FORMAT_VERSION: u32 = 0;
+    const MIGRATIONS: &'static [Migration] = &[];
+}
+
+//The network inside is encoded separately with its own header, so it migrates on its own
+impl Payload for crate::trainer::checkpoint::TrainingCheckpoint {
+    const KIND: PayloadKind = PayloadKind::TrainingCheckpoint;
+    const FORMAT_VERSION: u32 = 0;
+    const MIGRATIONS: &'static [Migration] = &[];
+}
+
+//perceptron::Network as it was saved before the header, headerless files always hold one
+//Layers kept their layout, so they are read with the current layer types
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct NetworkV0 {
+    layers: Vec<Box<dyn layers::NetworkLayer>>,
+    cost_function: CostV0,
+    output_size: usize,
+}
+
+//Cost functions that existed before the header, as a typetag map of type and dimension
+enum CostV0 {
+    SquaredError(usize),
+    CrossEntropy(usize),
+}
+
+impl<'de> Deserialize<'de> for CostV0 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(CostV0Visitor)
+    }
+}
+
+struct CostV0Visitor;
+
+impl<'de> Visitor<'de> for CostV0Visitor {
+    type Value = CostV0;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a SquaredError or CrossEntropy cost function")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<CostV0, A::Error> {
+        let (_, cost_type): (String, String) = map.next_entry()?.ok_or_else(|| de::Error::missing_field("type"))?;
+        let (_, dimension): (String, usize) = map.next_entry()?.ok_or_else(|| de::Error::missing_field("dimension"))?;
+        match cost_type.as_str() {
+            "SquaredError" => Ok(CostV0::SquaredError(dimension)),
+            "CrossEntropy" => Ok(CostV0::CrossEntropy(dimension)),
+            _ => Err(de::Error::unknown_variant(&cost_type, &["SquaredError", "CrossEntropy"])),
+        }
+    }
+}
+
+//Field order has to match perceptron::Network
+#[derive(Serialize)]
+struct NetworkV1 {
+    layers: Vec<Box<dyn layers::NetworkLayer>>,
+    cost_function: Box<dyn cost::CostFunction>,
+    output_size: usize,
+    regularisation: Vec<Option<super::Regularisation>>,
+}
+
+//Version 1 added the regularisation of every layer and the class weights and label smoothing of cross entropy
+fn migrate_headerless(payload: Vec<u8>) -> Vec<u8> {
+    let network: NetworkV0 = bincode::deserialize(&payload).unwrap_or_else(|e| panic!("Headerless file is not a saved network: {}", e));
+    let cost_function: Box<dyn cost::CostFunction> = match network.cost_function {
+        CostV0::SquaredError(dimension) => Box::new(cost::squarederror::SquaredError {
+            dimension,
+        }),
+        CostV0::CrossEntropy(dimension) => Box::new(cost::crossentropy::CrossEntropy {
+            dimension,
+            class_weight: vec![1.0; dimension],
+            smoothing: 0.0,
+        }),
+    };
+    let network = NetworkV1 {
+        regularisation: vec![None; network.layers.len()],
+        layers: network.layers,
+        cost_function,
+        output_size: network.output_size,
+    };
+    bincode::serialize(&network).unwrap()
+}
+
+pub fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325u64, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+pub fn encode<T: Payload>(value: &T) -> Vec<u8> {
+    let payload = bincode::serialize(value).unwrap();
+    let header = Header {
+        magic: MAGIC,
+        kind: T::KIND,
+        format_version: T::FORMAT_VERSION,
+        crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        payload_size: payload.len() as u64,
+        checksum: checksum(&payload),
+    };
+    let mut bytes = bincode::serialize(&header).unwrap();
+    bytes.extend(payload);
+    bytes
+}
+
+//Header of bytes, None for headerless files
+pub fn read_header(bytes: &[u8]) -> Option<(Header, usize)> {
+    if !bytes.starts_with(&MAGIC) {
+        return None;
+    }
+    let header: Header = bincode::deserialize(bytes).unwrap_or_else(|e| panic!("Corrupt model header: {}", e));
+    let header_size = bincode::serialized_size(&header).unwrap() as usize;
+    Some((header, header_size))
+}
+
+//Checks the header, migrates older payloads to the current format and deserializes them
+pub fn decode<T: Payload>(bytes: &[u8], filelocation: &str) -> T {
+    let (format_version, crate_version, mut payload) = match read_header(bytes) {
+        Some((header, header_size)) => {
+            assert_eq!(header.kind, T::KIND, "{} holds a {:?}, not a {:?}", filelocation, header.kind, T::KIND);
+            let payload = &bytes[header_size..];
+            assert_eq!(payload.len() as u64, header.payload_size, "{} is truncated, expected {} payload bytes but found {}", filelocation, header.payload_size, payload.len());
+            assert_eq!(checksum(payload), header.checksum, "{} is corrupt, the checksum does not match", filelocation);
+            assert!(header.format_version <= T::FORMAT_VERSION, "{} has {:?} format version {} written by version {}, this version only reads up to format version {}",
+                    filelocation, T::KIND, header.format_version, header.crate_version, T::FORMAT_VERSION);
+            (header.format_version, header.crate_version, payload.to_vec())
+        },
+        None => {
+            assert_eq!(T::KIND, PayloadKind::Network, "{} has no header, only a Network was ever saved without one, not a {:?}", filelocation, T::KIND);
+            (0, "unknown".to_string(), bytes.to_vec())
+        },
+    };
+
+    debug_assert_eq!(T::MIGRATIONS.len(), T::FORMAT_VERSION as usize, "{:?} needs one migration per format version", T::KIND);
+    for migration in T::MIGRATIONS[format_version as usize..].iter() {
+        payload = migration(payload);
+    }
+
+    bincode::deserialize(&payload).unwrap_or_else(|e| {
+        panic!("Could not read {} (format version {}, written by version {}): {}", filelocation, format_version, crate_version, e)
+    })
+}
+
+pub fn save_to_file<T: Payload>(value: &T, filelocation: &str) {
+    super::write_atomic(&encode(value), filelocation);
+}
+
+pub fn load_from_file<T: Payload>(filelocation: &str) -> T {
+    let bytes = std::fs::read(filelocation).unwrap_or_else(|e| panic!("Could not open {}: {}", filelocation, e));
+    decode(&bytes, filelocation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serializer;
+    use serde::ser::SerializeMap;
+    use crate::network::perceptron::Network;
+    use crate::network::graph::{GraphBuilder, GraphNetwork};
+    use crate::network::{CostFunction, LayerType};
+    use crate::network::layers::{fullyconnected::FullyConnected, relu::Relu};
+
+    //Writes the cost the way typetag did before the header
+    impl Serialize for CostV0 {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let (cost_type, dimension) = match self {
+                CostV0::SquaredError(dimension) => ("SquaredError", dimension),
+                CostV0::CrossEntropy(dimension) => ("CrossEntropy", dimension),
+            };
+            let mut map = serializer.serialize_map(Some(2))?;
+            map.serialize_entry("type", cost_type)?;
+            map.serialize_entry("dimension", dimension)?;
+            map.end()
+        }
+    }
+
+    fn baseline_layers() -> Vec<Box<dyn layers::NetworkLayer>> {
+        vec![
+            Box::new(FullyConnected {
+                weights: vec![0.5, -1.25, 2.0, 0.0, 3.5, -0.75],
+                output_dimension: 2,
+                input_dimension: 2,
+            }),
+            Box::new(Relu {
+                dimension: 2,
+            }),
+        ]
+    }
+
+    fn baseline_payload(cost_function: CostV0) -> Vec<u8> {
+        bincode::serialize(&NetworkV0 {
+            layers: baseline_layers(),
+            cost_function,
+            output_size: 2,
+        }).unwrap()
+    }
+
+    #[test]
+    fn headerless_cross_entropy_network_gets_defaults() {
+        let network: Network = decode(&baseline_payload(CostV0::CrossEntropy(2)), "baseline.bin");
+        let expected = NetworkV1 {
+            layers: baseline_layers(),
+            cost_function: Box::new(cost::crossentropy::CrossEntropy {
+                dimension: 2,
+                class_weight: vec![1.0, 1.0],
+                smoothing: 0.0,
+            }),
+            output_size: 2,
+            regularisation: vec![None, None],
+        };
+        assert_eq!(bincode::serialize(&network).unwrap(), bincode::serialize(&expected).unwrap());
+    }
+
+    #[test]
+    fn headerless_squared_error_network_gets_defaults() {
+        let network: Network = decode(&baseline_payload(CostV0::SquaredError(2)), "baseline.bin");
+        let expected = NetworkV1 {
+            layers: baseline_layers(),
+            cost_function: Box::new(cost::squarederror::SquaredError {
+                dimension: 2,
+            }),
+            output_size: 2,
+            regularisation: vec![None, None],
+        };
+        assert_eq!(bincode::serialize(&network).unwrap(), bincode::serialize(&expected).unwrap());
+    }
+
+    #[test]
+    fn encoded_network_round_trips() {
+        let network: Network = decode(&baseline_payload(CostV0::CrossEntropy(2)), "baseline.bin");
+        let bytes = encode(&network);
+        let (header, _) = read_header(&bytes).unwrap();
+        assert_eq!(header.kind, PayloadKind::Network);
+        assert_eq!(header.format_version, <Network as Payload>::FORMAT_VERSION);
+        let decoded: Network = decode(&bytes, "network.bin");
+        assert_eq!(bincode::serialize(&decoded).unwrap(), bincode::serialize(&network).unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "the checksum does not match")]
+    fn corrupt_payload_is_rejected() {
+        let network: Network = decode(&baseline_payload(CostV0::SquaredError(2)), "baseline.bin");
+        let mut bytes = encode(&network);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let _: Network = decode(&bytes, "network.bin");
+    }
+
+    #[test]
+    #[should_panic(expected = "network.bin holds a Network, not a GraphNetwork")]
+    fn kind_mismatch_is_rejected() {
+        let network: Network = decode(&baseline_payload(CostV0::SquaredError(2)), "baseline.bin");
+        let _: GraphNetwork = decode(&encode(&network), "network.bin");
+    }
+
+    #[test]
+    #[should_panic(expected = "only a Network was ever saved without one, not a GraphNetwork")]
+    fn headerless_file_is_only_read_as_a_network() {
+        let _: GraphNetwork = decode(&baseline_payload(CostV0::SquaredError(2)), "baseline.bin");
+    }
+
+    #[test]
+    fn graph_network_round_trips_with_its_own_kind() {
+        let mut graph = GraphBuilder::new(2);
+        let output = graph.layer(graph.input(), LayerType::Relu);
+        let network = GraphNetwork::new(graph, output, CostFunction::SquaredError);
+        let bytes = encode(&network);
+        let (header, _) = read_header(&bytes).unwrap();
+        assert_eq!(header.kind, PayloadKind::GraphNetwork);
+        assert_eq!(header.format_version, <GraphNetwork as Payload>::FORMAT_VERSION);
+        let decoded: GraphNetwork = decode(&bytes, "graph.bin");
+        assert_eq!(bincode::serialize(&decoded).unwrap(), bincode::serialize(&network).unwrap());
+    }
+}
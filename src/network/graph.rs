@@ -0,0 +1,762 @@
+use crate::pipelines;
+
+use serde::{Serialize, Deserialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::layers;
+use super::cost;
+
+//Handle to a node of a graph being built
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NodeId(usize);
+
+#[derive(Serialize, Deserialize)]
+enum Operation {
+    Input,
+    Layer(Box<dyn layers::NetworkLayer>),
+    //Elementwise sum of equally sized inputs
+    Add,
+    //Features of the inputs stacked in order
+    Concat,
+    //Features of the input starting at the offset
+    Split(usize),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Node {
+    operation: Operation,
+    inputs: Vec<usize>,
+    output_size: usize,
+}
+
+//Nodes can only take existing nodes as inputs so the graph is always acyclic
+#[allow(dead_code)]
+pub struct GraphBuilder {
+    nodes: Vec<Node>,
+    shapes: Vec<super::Shape>,
+    softmax_nodes: Vec<usize>,
+}
+
+#[allow(dead_code)]
+impl GraphBuilder {
+    pub fn new<S: Into<super::Shape>>(input_shape: S) -> Self {
+        let input_shape: super::Shape = input_shape.into();
+        GraphBuilder {
+            nodes: vec![Node {
+                operation: Operation::Input,
+                inputs: Vec::new(),
+                output_size: input_shape.size(),
+            }],
+            shapes: vec![input_shape],
+            softmax_nodes: Vec::new(),
+        }
+    }
+
+    pub fn input(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    pub fn size(&self, node: NodeId) -> usize {
+        self.nodes[node.0].output_size
+    }
+
+    pub fn shape(&self, node: NodeId) -> &super::Shape {
+        &self.shapes[node.0]
+    }
+
+    pub fn layer(&mut self, input: NodeId, layer_type: super::LayerType) -> NodeId {
+        let is_softmax = matches!(layer_type, super::LayerType::Softmax);
+        let layer_name = format!("{:?}", layer_type);
+        let (output_shape, layer) = match layers::generate_layer(self.shape(input), layer_type) {
+            Ok(generated) => generated,
+            Err(e) => panic!("Node {} ({}) can't take input of shape {}: {}", self.nodes.len(), layer_name, self.shape(input), e),
+        };
+        if is_softmax {
+            self.softmax_nodes.push(self.nodes.len());
+        }
+        self.push(Operation::Layer(layer), vec![input], output_shape)
+    }
+
+    pub fn add(&mut self, inputs: Vec<NodeId>) -> NodeId {
+        assert!(inputs.len() > 1, "Add node needs at least two inputs");
+        let output_shape = self.shape(inputs[0]).clone();
+        for &input in inputs.iter() {
+            assert_eq!(self.shape(input), &output_shape, "Add node {} inputs must be the same shape", self.nodes.len());
+        }
+        self.push(Operation::Add, inputs, output_shape)
+    }
+
+    pub fn concat(&mut self, inputs: Vec<NodeId>) -> NodeId {
+        assert!(inputs.len() > 1, "Concat node needs at least two inputs");
+        let output_size: usize = inputs.iter().map(|&input| self.size(input)).sum();
+        self.push(Operation::Concat, inputs, super::Shape::from(output_size))
+    }
+
+    pub fn split(&mut self, input: NodeId, offset: usize, size: usize) -> NodeId {
+        assert!(offset + size <= self.size(input), "Split node runs past the end of its input");
+        self.push(Operation::Split(offset), vec![input], super::Shape::from(size))
+    }
+
+    fn push(&mut self, operation: Operation, inputs: Vec<NodeId>, output_shape: super::Shape) -> NodeId {
+        self.nodes.push(Node {
+            operation,
+            inputs: inputs.into_iter().map(|input| input.0).collect(),
+            output_size: output_shape.size(),
+        });
+        self.shapes.push(output_shape);
+        NodeId(self.nodes.len() - 1)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GraphNetwork {
+    nodes: Vec<Node>,
+    order: Vec<usize>,
+    cost_function: Box<dyn cost::CostFunction>,
+    output_size: usize,
+}
+
+#[allow(dead_code)]
+impl GraphNetwork {
+    pub fn new(graph: GraphBuilder, output: NodeId, cost: super::CostFunction) -> Self {
+        let GraphBuilder { nodes, softmax_nodes, .. } = graph;
+        let output_size = nodes[output.0].output_size;
+
+        //Depth first post order from the output, nodes that don't reach it are never run
+        let mut order: Vec<usize> = Vec::new();
+        let mut visited = vec![false; nodes.len()];
+        let mut stack: Vec<(usize, usize)> = vec![(output.0, 0)];
+        visited[output.0] = true;
+        while let Some((node, next_input)) = stack.pop() {
+            match nodes[node].inputs.get(next_input) {
+                Some(&input) => {
+                    stack.push((node, next_input + 1));
+                    if !visited[input] {
+                        visited[input] = true;
+                        stack.push((input, 0));
+                    }
+                },
+                None => order.push(node),
+            }
+        }
+
+        let cost_function = match softmax_nodes.contains(&output.0) {
+            true => cost::generate_softmax_cost(output_size, cost),
+            false => cost::generate_cost(output_size, cost),
+        };
+
+        GraphNetwork {
+            nodes,
+            order,
+            cost_function,
+            output_size,
+        }
+    }
+
+    //Data is indexed by node, nodes without parameters have no data
+    pub fn get_topology(&self) -> Vec<Vec<(usize, usize)>> {
+        let mut vec: Vec<Vec<(usize, usize)>> = Vec::new();
+        for node in &self.nodes {
+            match &node.operation {
+                Operation::Layer(layer) => vec.push(layer.get_topology()),
+                _ => vec.push(Vec::new()),
+            }
+        }
+
+        //Return
+        vec
+    }
+
+    pub fn save_to_file(&self, filelocation: &str) {
+        super::format::save_to_file(self, filelocation);
+    }
+
+    pub fn load_from_file(filelocation: &str) -> Self {
+        super::format::load_from_file(filelocation)
+    }
+
+    pub fn load_to_gpu(&self, anchor: &pipelines::Device,) -> Vec<Vec<wgpu::Buffer>> {
+        let mut vec: Vec<Vec<wgpu::Buffer>> = Vec::new();
+        for node in &self.nodes {
+            match &node.operation {
+                Operation::Layer(layer) => vec.push(layer.load_to_gpu(anchor)),
+                _ => vec.push(Vec::new()),
+            }
+        }
+        vec
+    }
+
+    pub fn save_from_gpu(&mut self, anchor: &pipelines::Device, data: &Vec<Vec<wgpu::Buffer>>) {
+        let iter = self.nodes.iter_mut().zip(data);
+        for (node, node_data) in iter {
+            if let Operation::Layer(layer) = &mut node.operation {
+                layer.save_from_gpu(anchor, node_data);
+            }
+        }
+    }
+
+    pub fn feedforward<T: bytemuck::Pod>(&self,
+                                         input: &[T],
+                                         network_data: &[Vec<wgpu::Buffer>],
+                                         anchor: &pipelines::Device,
+                                         batch_size: usize,) -> wgpu::Buffer {
+        let queue = &anchor.queue;
+        let device = &anchor.device;
+
+        //Load input to gpu
+        self.check_input(input);
+        let input_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(input),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        //Feed input through nodes to get output
+        let output_buffer = self.forward_nodes(&self.order, input_buffer, network_data, anchor, &mut encoder, batch_size);
+
+        //Submit encoder
+        queue.submit(Some(encoder.finish()));
+
+        //Return
+        output_buffer
+    }
+
+    pub fn cost<I: bytemuck::Pod, T: bytemuck::Pod>(&self,
+                                  input: &[I],
+                                  labels: &[T],
+                                  network_data: &[Vec<wgpu::Buffer>],
+                                  anchor: &pipelines::Device,
+                                  batch_size: usize,
+                                  take_mean: bool) -> wgpu::Buffer {
+        let queue = &anchor.queue;
+        let device = &anchor.device;
+
+        //Load data to gpu
+        self.check_input(input);
+        let input_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(input),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        let label_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(labels),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        //Feed input through nodes not fused into the cost function
+        let unfused_nodes = &self.order[..self.order.len() - self.cost_function.fused_layers()];
+        let prediction = self.forward_nodes(unfused_nodes, input_buffer, network_data, anchor, &mut encoder, batch_size);
+
+        //Compute cost
+        let item_costs = self.cost_function.cost(
+            &prediction,
+            &label_buffer,
+            anchor,
+            &mut encoder,
+            batch_size,
+        );
+        match take_mean {
+            true =>{
+                //Create mean pipeline
+                let mean_uniforms = pipelines::uniform_buffer(anchor, &[1_u32, batch_size as u32]);
+                let mean_pipeline = pipelines::batchmean::Pipeline::new::<f32>(anchor, (
+                        &mean_uniforms,
+                        &item_costs,
+                    ),
+                    1,
+                    batch_size,
+                );
+
+                //Run mean pipeline
+                mean_pipeline.run(&mut encoder, 1, batch_size);
+
+                //Submit encoder
+                queue.submit(Some(encoder.finish()));
+
+                //Return
+                mean_pipeline.output_buffer
+            },
+            false =>{
+                //Submit encoder
+                queue.submit(Some(encoder.finish()));
+
+                //Return
+                item_costs
+            },
+        }
+    }
+
+    pub fn backprop<I: bytemuck::Pod, T: bytemuck::Pod>(&self,
+                                      input: &[I],
+                                      labels: &[T],
+                                      network_data: &mut [Vec<wgpu::Buffer>],
+                                      anchor: &pipelines::Device,
+                                      batch_size: usize,) -> Vec<Vec<Option<wgpu::Buffer>>> {
+        let queue = &anchor.queue;
+        let device = &anchor.device;
+
+        //Load data to gpu
+        self.check_input(input);
+        let input_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(input),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        let label_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(labels),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        //Feed input through nodes to get info for backprop
+        let unfused_nodes = &self.order[..self.order.len() - self.cost_function.fused_layers()];
+        let mut outputs: Vec<Option<wgpu::Buffer>> = self.nodes.iter().map(|_| None).collect();
+        let mut intermediate_values: Vec<Vec<wgpu::Buffer>> = self.nodes.iter().map(|_| Vec::new()).collect();
+        outputs[0] = Some(input_buffer);
+        for &index in unfused_nodes {
+            let node = &self.nodes[index];
+            let output = match &node.operation {
+                Operation::Input => continue,
+                Operation::Layer(layer) => {
+                    let (output, data) = layer.forward_for_backprop(
+                        outputs[node.inputs[0]].as_ref().unwrap(),
+                        &mut network_data[index],
+                        anchor,
+                        &mut encoder,
+                        batch_size,
+                    );
+                    intermediate_values[index] = data;
+                    output
+                },
+                _ => self.merge_forward(node, &outputs, anchor, &mut encoder, batch_size),
+            };
+            outputs[index] = Some(output);
+        }
+        let prediction = outputs[*unfused_nodes.last().unwrap()].take().unwrap();
+
+        //Layers keep their input, shared inputs are copied rather than moved
+        let consumers = self.consumers(unfused_nodes);
+        for &index in unfused_nodes {
+            let node = &self.nodes[index];
+            if let Operation::Layer(_) = node.operation {
+                let input = node.inputs[0];
+                let layer_input = match consumers[input] {
+                    1 => outputs[input].take().unwrap(),
+                    _ => pipelines::copy_buffer(outputs[input].as_ref().unwrap(), self.nodes[input].output_size * batch_size, anchor, &mut encoder),
+                };
+                intermediate_values[index].push(layer_input);
+            }
+        }
+
+        //Perform backprop
+        let mut grads: Vec<Option<wgpu::Buffer>> = self.nodes.iter().map(|_| None).collect();
+        grads[*unfused_nodes.last().unwrap()] = Some(self.cost_function.cost_prime(
+            &prediction,
+            &label_buffer,
+            anchor,
+            &mut encoder,
+            batch_size,
+        ));
+
+        //Fused and parameterless nodes have no grads
+        let mut backprop_values: Vec<Vec<Option<wgpu::Buffer>>> = self.nodes.iter().map(|_| Vec::new()).collect();
+        for &index in unfused_nodes.iter().rev() {
+            let node = &self.nodes[index];
+            let grad = match grads[index].take() {
+                Some(grad) => grad,
+                None => continue,
+            };
+
+            //Gradients for each input of the node
+            let mut input_grads: Vec<(usize, wgpu::Buffer)> = Vec::with_capacity(node.inputs.len());
+            match &node.operation {
+                Operation::Input => continue,
+                Operation::Layer(layer) => {
+                    let (layer_input_grad, layer_grads) = layer.backprop(
+                        &grad,
+                        &network_data[index],
+                        &intermediate_values[index],
+                        anchor,
+                        &mut encoder,
+                        batch_size,
+                    );
+                    backprop_values[index] = layer_grads;
+                    input_grads.push((node.inputs[0], layer_input_grad));
+                },
+                Operation::Add => {
+                    for &input in node.inputs.iter() {
+                        input_grads.push((input, pipelines::copy_buffer(&grad, node.output_size * batch_size, anchor, &mut encoder)));
+                    }
+                },
+                Operation::Concat => {
+                    let mut offset = 0;
+                    for &input in node.inputs.iter() {
+                        let input_size = self.nodes[input].output_size;
+
+                        //Create split pipeline
+                        let split_uniforms = pipelines::uniform_buffer(anchor, &[node.output_size as u32, input_size as u32, batch_size as u32, offset as u32]);
+                        let split_pipeline = pipelines::splitrows::Pipeline::new::<f32>(anchor, (
+                                &split_uniforms,
+                                &grad,
+                            ),
+                            node.output_size,
+                            input_size,
+                            batch_size,
+                        );
+
+                        //Run split pipeline
+                        split_pipeline.run(&mut encoder, node.output_size, input_size, batch_size);
+
+                        input_grads.push((input, split_pipeline.output_buffer));
+                        offset += input_size;
+                    }
+                },
+                Operation::Split(offset) => {
+                    let input_size = self.nodes[node.inputs[0]].output_size;
+
+                    //Create pad pipeline
+                    let pad_uniforms = pipelines::uniform_buffer(anchor, &[input_size as u32, node.output_size as u32, batch_size as u32, *offset as u32]);
+                    let pad_pipeline = pipelines::padrows::Pipeline::new::<f32>(anchor, (
+                            &pad_uniforms,
+                            &grad,
+                        ),
+                        input_size,
+                        node.output_size,
+                        batch_size,
+                    );
+
+                    //Run pad pipeline
+                    pad_pipeline.run(&mut encoder, input_size, node.output_size, batch_size);
+
+                    input_grads.push((node.inputs[0], pad_pipeline.output_buffer));
+                },
+            }
+
+            //Accumulate gradients at nodes with several consumers
+            for (input, input_grad) in input_grads.into_iter() {
+                let accumulated = match grads[input].take() {
+                    Some(existing) => {
+                        let input_size = self.nodes[input].output_size;
+
+                        //Create accumulate pipeline
+                        let accumulate_uniforms = pipelines::uniform_buffer(anchor, &[input_size as u32, batch_size as u32]);
+                        let accumulate_pipeline = pipelines::elementadd::Pipeline::new::<f32>(anchor, (
+                                &accumulate_uniforms,
+                                &existing,
+                                &input_grad,
+                            ),
+                            input_size,
+                            batch_size,
+                        );
+
+                        //Run accumulate pipeline
+                        accumulate_pipeline.run(&mut encoder, input_size, batch_size);
+
+                        accumulate_pipeline.output_buffer
+                    },
+                    None => input_grad,
+                };
+                grads[input] = Some(accumulated);
+            }
+        }
+
+        //Submit encoder
+        queue.submit(Some(encoder.finish()));
+
+        //Return
+        backprop_values
+    }
+
+    //Number of reads of each node's output by the given nodes
+    fn consumers(&self, nodes: &[usize]) -> Vec<usize> {
+        let mut consumers = vec![0usize; self.nodes.len()];
+        for &index in nodes {
+            for &input in self.nodes[index].inputs.iter() {
+                consumers[input] += 1;
+            }
+        }
+        consumers
+    }
+
+    //Every layer reading the input node sees the raw input
+    fn check_input<I: bytemuck::Pod>(&self, input: &[I]) {
+        for node in &self.nodes {
+            if let Operation::Layer(layer) = &node.operation {
+                if node.inputs.contains(&0) {
+                    layer.check_input(bytemuck::cast_slice(input));
+                }
+            }
+        }
+    }
+
+    fn forward_nodes(&self,
+                     nodes: &[usize],
+                     input_buffer: wgpu::Buffer,
+                     network_data: &[Vec<wgpu::Buffer>],
+                     anchor: &pipelines::Device,
+                     encoder: &mut wgpu::CommandEncoder,
+                     batch_size: usize,) -> wgpu::Buffer {
+        let mut outputs: Vec<Option<wgpu::Buffer>> = self.nodes.iter().map(|_| None).collect();
+        outputs[0] = Some(input_buffer);
+        for &index in nodes {
+            let node = &self.nodes[index];
+            let output = match &node.operation {
+                Operation::Input => continue,
+                Operation::Layer(layer) => {
+                    layer.forward(
+                        outputs[node.inputs[0]].as_ref().unwrap(),
+                        &network_data[index],
+                        anchor,
+                        encoder,
+                        batch_size,
+                    )
+                },
+                _ => self.merge_forward(node, &outputs, anchor, encoder, batch_size),
+            };
+            outputs[index] = Some(output);
+        }
+
+        //Return
+        outputs[*nodes.last().unwrap()].take().unwrap()
+    }
+
+    fn merge_forward(&self,
+                     node: &Node,
+                     outputs: &[Option<wgpu::Buffer>],
+                     anchor: &pipelines::Device,
+                     encoder: &mut wgpu::CommandEncoder,
+                     batch_size: usize,) -> wgpu::Buffer {
+        let mut inputs = node.inputs.iter().map(|&input| (input, outputs[input].as_ref().unwrap()));
+        match &node.operation {
+            Operation::Add => {
+                let (_, first) = inputs.next().unwrap();
+                let (_, second) = inputs.next().unwrap();
+
+                //Create add pipeline
+                let add_uniforms = pipelines::uniform_buffer(anchor, &[node.output_size as u32, batch_size as u32]);
+                let add_pipeline = pipelines::elementadd::Pipeline::new::<f32>(anchor, (
+                        &add_uniforms,
+                        first,
+                        second,
+                    ),
+                    node.output_size,
+                    batch_size,
+                );
+
+                //Run add pipeline
+                add_pipeline.run(encoder, node.output_size, batch_size);
+
+                //Fold in remaining inputs
+                inputs.fold(add_pipeline.output_buffer, |total, (_, next)| {
+                    let add_pipeline = pipelines::elementadd::Pipeline::new::<f32>(anchor, (
+                            &add_uniforms,
+                            &total,
+                            next,
+                        ),
+                        node.output_size,
+                        batch_size,
+                    );
+                    add_pipeline.run(encoder, node.output_size, batch_size);
+                    add_pipeline.output_buffer
+                })
+            },
+            Operation::Concat => {
+                let (first_index, first) = inputs.next().unwrap();
+                let (second_index, second) = inputs.next().unwrap();
+                let first_size = self.nodes[first_index].output_size;
+                let second_size = self.nodes[second_index].output_size;
+
+                //Create concat pipeline
+                let concat_uniforms = pipelines::uniform_buffer(anchor, &[first_size as u32, second_size as u32, batch_size as u32]);
+                let concat_pipeline = pipelines::concatrows::Pipeline::new::<f32>(anchor, (
+                        &concat_uniforms,
+                        first,
+                        second,
+                    ),
+                    first_size,
+                    second_size,
+                    batch_size,
+                );
+
+                //Run concat pipeline
+                concat_pipeline.run(encoder, first_size, second_size, batch_size);
+
+                //Fold in remaining inputs
+                let (output, _) = inputs.fold((concat_pipeline.output_buffer, first_size + second_size), |(total, total_size), (next_index, next)| {
+                    let next_size = self.nodes[next_index].output_size;
+                    let concat_uniforms = pipelines::uniform_buffer(anchor, &[total_size as u32, next_size as u32, batch_size as u32]);
+                    let concat_pipeline = pipelines::concatrows::Pipeline::new::<f32>(anchor, (
+                            &concat_uniforms,
+                            &total,
+                            next,
+                        ),
+                        total_size,
+                        next_size,
+                        batch_size,
+                    );
+                    concat_pipeline.run(encoder, total_size, next_size, batch_size);
+                    (concat_pipeline.output_buffer, total_size + next_size)
+                });
+                output
+            },
+            Operation::Split(offset) => {
+                let (input_index, input) = inputs.next().unwrap();
+                let input_size = self.nodes[input_index].output_size;
+
+                //Create split pipeline
+                let split_uniforms = pipelines::uniform_buffer(anchor, &[input_size as u32, node.output_size as u32, batch_size as u32, *offset as u32]);
+                let split_pipeline = pipelines::splitrows::Pipeline::new::<f32>(anchor, (
+                        &split_uniforms,
+                        input,
+                    ),
+                    input_size,
+                    node.output_size,
+                    batch_size,
+                );
+
+                //Run split pipeline
+                split_pipeline.run(encoder, input_size, node.output_size, batch_size);
+
+                //Return
+                split_pipeline.output_buffer
+            },
+            _ => unreachable!("Only merge nodes are run here"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{CostFunction, LayerType};
+
+    //Every node comes after the nodes it reads
+    fn assert_topological(network: &GraphNetwork) {
+        for (position, &index) in network.order.iter().enumerate() {
+            for input in network.nodes[index].inputs.iter() {
+                assert!(network.order[..position].contains(input), "Node {} runs before its input {} in {:?}", index, input, network.order);
+            }
+        }
+    }
+
+    //input -> a -> b, a -> c, b + c -> d
+    fn diamond() -> (GraphBuilder, [NodeId; 4]) {
+        let mut graph = GraphBuilder::new(4);
+        let a = graph.layer(graph.input(), LayerType::Relu);
+        let b = graph.layer(a, LayerType::Relu);
+        let c = graph.layer(a, LayerType::Relu);
+        let d = graph.add(vec![b, c]);
+        (graph, [a, b, c, d])
+    }
+
+    #[test]
+    fn diamond_runs_in_topological_order() {
+        let (graph, [a, b, c, d]) = diamond();
+        let network = GraphNetwork::new(graph, d, CostFunction::SquaredError);
+
+        assert_topological(&network);
+        let mut order = network.order.clone();
+        order.sort();
+        assert_eq!(order, vec![0, a.0, b.0, c.0, d.0]);
+        assert_eq!(network.order.last(), Some(&d.0));
+    }
+
+    #[test]
+    fn nodes_that_do_not_reach_the_output_are_skipped() {
+        let (mut graph, [_, b, c, d]) = diamond();
+        let dead_end = graph.layer(b, LayerType::Relu);
+        let unused_branch = graph.layer(graph.input(), LayerType::Relu);
+        let network = GraphNetwork::new(graph, d, CostFunction::SquaredError);
+
+        assert_topological(&network);
+        assert!(!network.order.contains(&dead_end.0));
+        assert!(!network.order.contains(&unused_branch.0));
+        assert!(network.order.contains(&c.0));
+    }
+
+    #[test]
+    fn output_before_the_end_of_the_graph_drops_later_nodes() {
+        let (graph, [a, b, c, d]) = diamond();
+        let network = GraphNetwork::new(graph, b, CostFunction::SquaredError);
+
+        assert_eq!(network.order, vec![0, a.0, b.0]);
+        assert!(!network.order.contains(&c.0));
+        assert!(!network.order.contains(&d.0));
+    }
+
+    #[test]
+    fn diamond_counts_both_reads_of_the_shared_node() {
+        let (graph, [a, b, c, d]) = diamond();
+        let network = GraphNetwork::new(graph, d, CostFunction::SquaredError);
+        let consumers = network.consumers(&network.order);
+
+        assert_eq!(consumers[0], 1);
+        assert_eq!(consumers[a.0], 2);
+        assert_eq!(consumers[b.0], 1);
+        assert_eq!(consumers[c.0], 1);
+        assert_eq!(consumers[d.0], 0);
+    }
+
+    #[test]
+    fn fan_out_counts_every_branch_and_repeated_inputs() {
+        let mut graph = GraphBuilder::new(4);
+        let branches: Vec<NodeId> = (0..3).map(|_| graph.layer(graph.input(), LayerType::Relu)).collect();
+        let doubled = graph.add(vec![branches[0], branches[0]]);
+        let output = graph.concat(vec![doubled, branches[1], branches[2]]);
+        let network = GraphNetwork::new(graph, output, CostFunction::SquaredError);
+        let consumers = network.consumers(&network.order);
+
+        assert_topological(&network);
+        assert_eq!(consumers[0], 3);
+        assert_eq!(consumers[branches[0].0], 2);
+        assert_eq!(consumers[branches[1].0], 1);
+        assert_eq!(consumers[doubled.0], 1);
+        assert_eq!(consumers[output.0], 0);
+    }
+
+    #[test]
+    fn consumers_only_count_the_given_nodes() {
+        let (graph, [a, b, c, d]) = diamond();
+        let network = GraphNetwork::new(graph, d, CostFunction::SquaredError);
+        //As when the nodes fused into the cost function are left out
+        let consumers = network.consumers(&[0, a.0, b.0]);
+
+        assert_eq!(consumers[a.0], 1);
+        assert_eq!(consumers[b.0], 0);
+        assert_eq!(consumers[c.0], 0);
+    }
+}
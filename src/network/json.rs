@@ -0,0 +1,323 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::path::Path;
+
+//Human readable export of anything serde can write, used to inspect and hand edit networks
+//Layers and costs keep their typetag "type" tags, so the document reads back into the same structs
+//JSON has no NaN or infinities, so a diverged network can't be exported
+
+//Where export puts the weights
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeightStorage {
+    //Every weight written as a JSON number
+    Inline,
+    //Parameter tensors of the layers are replaced by {"sidecar": [offset, length]} into a little endian f32 file next to the document
+    Sidecar,
+}
+
+const SIDECAR_KEY: &str = "sidecar";
+
+fn sidecar_location(filelocation: &str) -> String {
+    Path::new(filelocation).with_extension("weights").to_string_lossy().into_owned()
+}
+
+//Moves the float arrays of every layer in network["layers"] into weights
+//Only layers hold parameter tensors, float lists elsewhere such as class weights are hyperparameters and stay in the document
+//Integer lists such as shapes are written without a fraction, so they are never mistaken for a tensor
+fn extract_weights(network: &mut Value, weights: &mut Vec<f32>) {
+    let layers = match network.get_mut("layers") {
+        Some(Value::Array(layers)) => layers,
+        _ => return,
+    };
+    for layer in layers.iter_mut() {
+        let fields = match layer {
+            Value::Object(fields) => fields,
+            _ => continue,
+        };
+        for (_, field) in fields.iter_mut() {
+            let tensor: Vec<f32> = match field {
+                Value::Array(items) if !items.is_empty() => match items.iter().map(|item| match item {
+                    Value::Number(number) if number.is_f64() => number.as_f64().map(|weight| weight as f32),
+                    _ => None,
+                }).collect() {
+                    Some(tensor) => tensor,
+                    None => continue,
+                },
+                _ => continue,
+            };
+            *field = json!({SIDECAR_KEY: [weights.len(), tensor.len()]});
+            weights.extend(tensor);
+        }
+    }
+}
+
+fn insert_weights(value: &mut Value, weights: &[f32]) -> Result<(), String> {
+    let range = match value {
+        Value::Object(entries) if entries.len() == 1 && entries.contains_key(SIDECAR_KEY) => match &entries[SIDECAR_KEY] {
+            Value::Array(range) if range.len() == 2 => (range[0].as_u64().map(|offset| offset as usize), range[1].as_u64().map(|length| length as usize)),
+            _ => return Err("Sidecar references are [offset, length]".to_string()),
+        },
+        Value::Array(items) => return items.iter_mut().try_for_each(|item| insert_weights(item, weights)),
+        Value::Object(entries) => return entries.iter_mut().try_for_each(|(_, entry)| insert_weights(entry, weights)),
+        _ => return Ok(()),
+    };
+    match range {
+        (Some(offset), Some(length)) if offset + length <= weights.len() => {
+            *value = json!(weights[offset..offset + length]);
+            Ok(())
+        },
+        _ => Err(format!("Sidecar reference {:?} is outside the {} weights", range, weights.len())),
+    }
+}
+
+//Writes value under "network" with the crate version and, for sidecar storage, the weight file and its checksum
+pub fn export<T: Serialize + DeserializeOwned>(value: &T, filelocation: &str, storage: WeightStorage) {
+    let mut network = serde_json::to_value(value).unwrap_or_else(|e| panic!("Could not export {}: {}", filelocation, e));
+    //serde_json writes NaN and infinities as null, which then no longer reads back as a float
+    if let Err(e) = serde_json::from_value::<T>(network.clone()) {
+        panic!("Could not export {}: it holds NaN or infinite values, which JSON can't represent ({})", filelocation, e);
+    }
+
+    let sidecar = match storage {
+        WeightStorage::Inline => Value::Null,
+        WeightStorage::Sidecar => {
+            let mut weights = Vec::new();
+            extract_weights(&mut network, &mut weights);
+            let bytes: Vec<u8> = weights.iter().flat_map(|weight| weight.to_le_bytes()).collect();
+            let location = sidecar_location(filelocation);
+            super::write_atomic(&bytes, &location);
+            json!({
+                "file": Path::new(&location).file_name().unwrap().to_string_lossy(),
+                "length": weights.len(),
+                //As a string, JSON readers commonly lose integers above 2^53
+                "checksum": super::format::checksum(&bytes).to_string(),
+            })
+        },
+    };
+
+    let document = json!({
+        "crate_version": env!("CARGO_PKG_VERSION"),
+        "sidecar": sidecar,
+        "network": network,
+    });
+    super::write_atomic(serde_json::to_string_pretty(&document).unwrap().as_bytes(), filelocation);
+}
+
+//Reads a document written by export, the sidecar file is looked up next to it
+pub fn import<T: DeserializeOwned>(filelocation: &str) -> T {
+    let text = std::fs::read_to_string(filelocation).unwrap_or_else(|e| panic!("Could not open {}: {}", filelocation, e));
+    let mut document: Value = serde_json::from_str(&text).unwrap_or_else(|e| panic!("Could not parse {}: {}", filelocation, e));
+    let mut network = document.get_mut("network").map(Value::take).unwrap_or_else(|| panic!("{} has no network", filelocation));
+
+    if let Some(sidecar) = document.get("sidecar").filter(|sidecar| sidecar.is_object()) {
+        let file = sidecar.get("file").and_then(Value::as_str).unwrap_or_else(|| panic!("{} has no sidecar file name", filelocation));
+        let location = Path::new(filelocation).with_file_name(file);
+        let bytes = std::fs::read(&location).unwrap_or_else(|e| panic!("Could not open {}: {}", location.display(), e));
+        assert_eq!(Some(bytes.len() as u64 / 4), sidecar.get("length").and_then(Value::as_u64), "{} does not hold the expected number of weights", location.display());
+        let checksum = sidecar.get("checksum").and_then(Value::as_str).and_then(|checksum| checksum.parse::<u64>().ok());
+        assert_eq!(checksum, Some(super::format::checksum(&bytes)), "{} is corrupt, the checksum does not match", location.display());
+
+        let weights: Vec<f32> = bytes.chunks_exact(4).map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])).collect();
+        insert_weights(&mut network, &weights).unwrap_or_else(|e| panic!("Could not read {}: {}", filelocation, e));
+    }
+
+    serde_json::from_value(network).unwrap_or_else(|e| panic!("Could not read {}: {}", filelocation, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use crate::network::Regularisation;
+    use crate::network::layers::{self, attention, batchnorm, elementwise, embedding, fullyconnected, layernorm, prelu, recurrent, relu, reshape, softmax, transpose};
+    use crate::network::cost::{self, binarycrossentropy, binarycrossentropylogits, contrastive, crossentropy, focal, huber, kldivergence, logcosh,
+                               meanabsoluteerror, quantile, softmaxcrossentropy, softmaxfocal, sparsecrossentropy, sparsesoftmaxcrossentropy,
+                               squarederror, tripletmargin};
+
+    //Laid out like perceptron::Network, with a list of costs so every type fits in one document
+    #[derive(Serialize, Deserialize)]
+    struct Model {
+        layers: Vec<Box<dyn layers::NetworkLayer>>,
+        costs: Vec<Box<dyn cost::CostFunction>>,
+        regularisation: Vec<Option<Regularisation>>,
+    }
+
+    //Values across the f32 range that only survive if the text round trips exactly
+    fn tensor(length: usize, seed: f32) -> Vec<f32> {
+        (0..length).map(|i| ((i as f32 + seed) * 0.7371).sin() * 10f32.powi(i as i32 % 9 - 4)).collect()
+    }
+
+    fn every_layer() -> Vec<Box<dyn layers::NetworkLayer>> {
+        vec![
+            Box::new(fullyconnected::FullyConnected { weights: tensor(8, 1.0), output_dimension: 2, input_dimension: 3 }),
+            Box::new(embedding::Embedding { table: tensor(6, 2.0), vocab_size: 3, embedding_size: 2, token_count: 1, padding_index: Some(0) }),
+            Box::new(batchnorm::Batchnorm { gamma: tensor(2, 3.0), beta: tensor(2, 4.0), data_var: vec![1.0 / 3.0, f32::MIN_POSITIVE], data_mean: vec![-0.1, f32::MAX], batches_sampled: 7, dimension: 2 }),
+            Box::new(layernorm::LayerNorm { gamma: tensor(2, 5.0), beta: tensor(2, 6.0), dimension: 2 }),
+            Box::new(relu::Relu { dimension: 2 }),
+            Box::new(prelu::Prelu { slope: tensor(2, 7.0), dimension: 2 }),
+            Box::new(recurrent::Recurrent { cell: recurrent::RecurrentCell::Lstm, weights: tensor(40, 8.0), input_dimension: 2, hidden_dimension: 2, step_count: 3, return_sequences: true, stateful: false, state: Vec::new() }),
+            Box::new(attention::MultiHeadAttention {
+                query_weights: tensor(4, 9.0),
+                key_weights: tensor(4, 10.0),
+                value_weights: tensor(4, 11.0),
+                output_weights: tensor(4, 12.0),
+                model_dimension: 2,
+                head_count: 1,
+                sequence_length: 3,
+                causal: true,
+            }),
+            Box::new(reshape::Reshape { input_shape: vec![3, 2], output_shape: vec![6] }),
+            Box::new(transpose::Transpose { input_shape: vec![3, 2], permutation: vec![1, 0] }),
+            Box::new(elementwise::Elementwise { function: "x > 0.0 ? x : 0.1 * x".to_string(), derivative: "x > 0.0 ? 1.0 : 0.1".to_string(), dimension: 6 }),
+            Box::new(softmax::Softmax { dimension: 6 }),
+        ]
+    }
+
+    fn every_cost() -> Vec<Box<dyn cost::CostFunction>> {
+        vec![
+            Box::new(squarederror::SquaredError { dimension: 3 }),
+            Box::new(crossentropy::CrossEntropy { dimension: 3, class_weight: vec![0.5, 1.0, 2.0], smoothing: 0.1 }),
+            Box::new(softmaxcrossentropy::SoftmaxCrossEntropy { dimension: 3, class_weight: vec![1.0, 1.5, 0.25], smoothing: 0.0 }),
+            Box::new(sparsecrossentropy::SparseCrossEntropy { dimension: 3, class_weight: Vec::new(), smoothing: 0.05 }),
+            Box::new(sparsesoftmaxcrossentropy::SparseSoftmaxCrossEntropy { dimension: 3, class_weight: vec![3.0, 1.0, 1.0], smoothing: 0.2 }),
+            Box::new(binarycrossentropy::BinaryCrossEntropy { dimension: 3, pos_weight: vec![2.0, 1.0, 0.5] }),
+            Box::new(binarycrossentropylogits::BinaryCrossEntropyWithLogits { dimension: 3, pos_weight: Vec::new() }),
+            Box::new(huber::Huber { dimension: 3, delta: 1.35 }),
+            Box::new(meanabsoluteerror::MeanAbsoluteError { dimension: 3 }),
+            Box::new(logcosh::LogCosh { dimension: 3 }),
+            Box::new(quantile::Quantile { dimension: 3, tau: 0.9 }),
+            Box::new(kldivergence::KlDivergence { dimension: 3, temperature: 2.5, fused_layers: 1 }),
+            Box::new(focal::Focal { dimension: 3, gamma: 2.0, alpha: 0.25 }),
+            Box::new(softmaxfocal::SoftmaxFocal { dimension: 3, gamma: 1.5, alpha: 0.75 }),
+            Box::new(contrastive::Contrastive { dimension: 3, margin: 1.0 }),
+            Box::new(tripletmargin::TripletMargin { dimension: 3, margin: 0.3 }),
+        ]
+    }
+
+    fn model(layers: Vec<Box<dyn layers::NetworkLayer>>) -> Model {
+        let mut regularisation = vec![None; layers.len()];
+        regularisation[0] = Some(Regularisation { l1: 0.001, l2: 0.0001 });
+        Model { layers, costs: every_cost(), regularisation }
+    }
+
+    fn bytes(model: &Model) -> Vec<u8> {
+        bincode::serialize(model).unwrap()
+    }
+
+    fn temp_location(name: &str) -> String {
+        std::env::temp_dir().join(format!("rust_perceptron_{}_{}.json", name, std::process::id())).to_string_lossy().into_owned()
+    }
+
+    //Exports and imports model, returning the import and the document text
+    fn round_trip(model: &Model, name: &str, storage: WeightStorage) -> (Model, String) {
+        let location = temp_location(name);
+        export(model, &location, storage);
+        let text = std::fs::read_to_string(&location).unwrap();
+        let imported = import(&location);
+        let _ = std::fs::remove_file(&location);
+        let _ = std::fs::remove_file(sidecar_location(&location));
+        (imported, text)
+    }
+
+    fn read_network(text: &str) -> Model {
+        let mut document: Value = serde_json::from_str(text).unwrap();
+        serde_json::from_value(document["network"].take()).unwrap()
+    }
+
+    #[test]
+    fn every_layer_and_cost_round_trips_inline() {
+        let model = model(every_layer());
+        let (imported, text) = round_trip(&model, "inline", WeightStorage::Inline);
+        assert_eq!(bytes(&imported), bytes(&model));
+        let document: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(document.get("sidecar"), Some(&Value::Null));
+        assert!(!document["network"].to_string().contains(SIDECAR_KEY));
+    }
+
+    #[test]
+    fn every_layer_and_cost_round_trips_with_sidecar() {
+        let model = model(every_layer());
+        let (imported, text) = round_trip(&model, "sidecar", WeightStorage::Sidecar);
+        assert_eq!(bytes(&imported), bytes(&model));
+
+        let document: Value = serde_json::from_str(&text).unwrap();
+        let tensor_length = 8 + 6 + 2 * 4 + 2 * 2 + 2 + 40 + 4 * 4;
+        assert_eq!(document.get("sidecar").and_then(|sidecar| sidecar.get("length")), Some(&json!(tensor_length)));
+        //Class and positive weights are hyperparameters and stay readable in the document
+        let network = document.get("network").unwrap();
+        let costs = match network.get("costs") {
+            Some(Value::Array(costs)) => costs,
+            _ => panic!("costs missing from the document"),
+        };
+        assert!(matches!(costs[1].get("class_weight"), Some(Value::Array(weights)) if weights.len() == 3));
+        assert!(matches!(costs[5].get("pos_weight"), Some(Value::Array(weights)) if weights.len() == 3));
+        let layers = match network.get("layers") {
+            Some(Value::Array(layers)) => layers,
+            _ => panic!("layers missing from the document"),
+        };
+        assert!(matches!(layers[0].get("weights"), Some(weights) if weights.get(SIDECAR_KEY).is_some()));
+        assert!(matches!(layers[8].get("input_shape"), Some(Value::Array(shape)) if shape.len() == 2));
+    }
+
+    #[test]
+    #[should_panic(expected = "it holds NaN or infinite values, which JSON can't represent")]
+    fn non_finite_weights_are_refused() {
+        let model = model(vec![Box::new(fullyconnected::FullyConnected { weights: vec![0.5, f32::NAN, 1.0, -0.0], output_dimension: 2, input_dimension: 1 })]);
+        export(&model, &temp_location("non_finite"), WeightStorage::Sidecar);
+    }
+
+    #[test]
+    #[should_panic(expected = "it holds NaN or infinite values, which JSON can't represent")]
+    fn non_finite_hyperparameters_are_refused() {
+        let mut model = model(vec![Box::new(relu::Relu { dimension: 2 })]);
+        model.costs = vec![Box::new(huber::Huber { dimension: 2, delta: f32::INFINITY })];
+        export(&model, &temp_location("non_finite_hyperparameter"), WeightStorage::Inline);
+    }
+
+    #[test]
+    fn hand_edited_integers_read_as_f32() {
+        let expected = Model {
+            layers: vec![Box::new(fullyconnected::FullyConnected { weights: vec![1.0, -2.0, 0.0, 3.0], output_dimension: 2, input_dimension: 1 })],
+            costs: vec![
+                Box::new(crossentropy::CrossEntropy { dimension: 2, class_weight: vec![1.0, 4.0], smoothing: 0.0 }),
+                Box::new(huber::Huber { dimension: 2, delta: 2.0 }),
+            ],
+            regularisation: vec![Some(Regularisation { l1: 0.0, l2: 1.0 })],
+        };
+        let type_first = r#"{"network": {
+            "layers": [{"type": "FullyConnected", "weights": [1, -2, 0, 3], "output_dimension": 2, "input_dimension": 1}],
+            "costs": [{"type": "CrossEntropy", "dimension": 2, "class_weight": [1, 4], "smoothing": 0}, {"type": "Huber", "dimension": 2, "delta": 2}],
+            "regularisation": [{"l1": 0, "l2": 1}]
+        }}"#;
+        let type_last = r#"{"network": {
+            "layers": [{"weights": [1, -2, 0, 3], "output_dimension": 2, "input_dimension": 1, "type": "FullyConnected"}],
+            "costs": [{"dimension": 2, "class_weight": [1, 4], "smoothing": 0, "type": "CrossEntropy"}, {"dimension": 2, "delta": 2, "type": "Huber"}],
+            "regularisation": [{"l1": 0, "l2": 1}]
+        }}"#;
+        assert_eq!(bytes(&read_network(type_first)), bytes(&expected));
+        assert_eq!(bytes(&read_network(type_last)), bytes(&expected));
+    }
+
+    #[test]
+    fn escaped_strings_round_trip_in_layers() {
+        let model = model(vec![Box::new(elementwise::Elementwise {
+            function: "x < 0.0 ? \"neg\\ative\"\n\t\u{1f600}".to_string(),
+            derivative: "\u{1}".to_string(),
+            dimension: 1,
+        })]);
+        let (imported, _) = round_trip(&model, "escapes", WeightStorage::Inline);
+        assert_eq!(bytes(&imported), bytes(&model));
+    }
+
+    #[test]
+    #[should_panic(expected = "the checksum does not match")]
+    fn corrupt_sidecar_is_rejected() {
+        let location = temp_location("corrupt");
+        export(&model(every_layer()), &location, WeightStorage::Sidecar);
+        let mut weights = std::fs::read(sidecar_location(&location)).unwrap();
+        weights[0] ^= 1;
+        std::fs::write(sidecar_location(&location), weights).unwrap();
+        let _: Model = import(&location);
+    }
+}
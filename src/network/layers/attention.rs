@@ -0,0 +1,645 @@
+use crate::pipelines;
+
+use serde::{Serialize, Deserialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use futures::executor::block_on;
+
+//Each batch item is sequence_length tokens of model_dimension features.
+//Projection weights are model_dimension x model_dimension with heads taking contiguous slices of features.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MultiHeadAttention {
+    pub query_weights: Vec<f32>,
+    pub key_weights: Vec<f32>,
+    pub value_weights: Vec<f32>,
+    pub output_weights: Vec<f32>,
+    pub model_dimension: usize,
+    pub head_count: usize,
+    pub sequence_length: usize,
+    pub causal: bool,
+}
+
+struct ForwardOutput {
+    query: wgpu::Buffer,
+    key: wgpu::Buffer,
+    value: wgpu::Buffer,
+    attention: wgpu::Buffer,
+    heads: wgpu::Buffer,
+    output: wgpu::Buffer,
+}
+
+impl MultiHeadAttention {
+    fn attention_uniforms(&self, anchor: &pipelines::Device, batch_size: usize, causal: bool, scaled: bool) -> wgpu::Buffer {
+        let uniform_data = [
+            self.sequence_length as u32,
+            self.head_count as u32,
+            (self.model_dimension / self.head_count) as u32,
+            batch_size as u32,
+            causal as u32,
+            scaled as u32,
+        ];
+        anchor.device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: Some("Uniform Buffer"),
+                contents: bytemuck::bytes_of(&uniform_data),
+                usage: wgpu::BufferUsages::UNIFORM,
+            }
+        )
+    }
+
+    fn run_attention(&self,
+                     input: &wgpu::Buffer,
+                     layer_data: &[wgpu::Buffer],
+                     anchor: &pipelines::Device,
+                     encoder: &mut wgpu::CommandEncoder,
+                     batch_size: usize,) -> ForwardOutput {
+        let device = &anchor.device;
+        let model_size = self.model_dimension;
+        let token_count = self.sequence_length * batch_size;
+        let score_count = self.sequence_length * self.head_count * batch_size;
+
+        let mut gpu_data = layer_data.iter();
+        let layer_query = gpu_data.next().unwrap();
+        let layer_key = gpu_data.next().unwrap();
+        let layer_value = gpu_data.next().unwrap();
+        let layer_output = gpu_data.next().unwrap();
+
+        //Create projection pipelines
+        let projection_uniforms = {
+            let uniform_data = [model_size as u32, model_size as u32, token_count as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let query_pipeline = pipelines::multiplybytranspose::Pipeline::new::<f32>(anchor, (
+                &projection_uniforms,
+                layer_query,
+                input,
+            ),
+            model_size,
+            model_size,
+            token_count,
+        );
+
+        let key_pipeline = pipelines::multiplybytranspose::Pipeline::new::<f32>(anchor, (
+                &projection_uniforms,
+                layer_key,
+                input,
+            ),
+            model_size,
+            model_size,
+            token_count,
+        );
+
+        let value_pipeline = pipelines::multiplybytranspose::Pipeline::new::<f32>(anchor, (
+                &projection_uniforms,
+                layer_value,
+                input,
+            ),
+            model_size,
+            model_size,
+            token_count,
+        );
+
+        //Run projection pipelines
+        query_pipeline.run(encoder, model_size, model_size, token_count);
+        key_pipeline.run(encoder, model_size, model_size, token_count);
+        value_pipeline.run(encoder, model_size, model_size, token_count);
+
+        //Create scores pipeline
+        let scores_uniforms = self.attention_uniforms(anchor, batch_size, self.causal, true);
+        let scores_pipeline = pipelines::attentionscores::Pipeline::new::<f32>(anchor, (
+                &scores_uniforms,
+                &query_pipeline.output_buffer,
+                &key_pipeline.output_buffer,
+            ),
+            self.sequence_length,
+            self.head_count,
+            batch_size,
+        );
+
+        //Run scores pipeline
+        scores_pipeline.run(encoder, self.sequence_length, self.head_count, batch_size);
+
+        //Softmax over keys treats every query step of every head as its own column
+        let softmax = super::softmax::Softmax {
+            dimension: self.sequence_length,
+        };
+        let attention = super::NetworkLayer::forward(&softmax, &scores_pipeline.output_buffer, &Vec::new(), anchor, encoder, score_count);
+
+        //Create apply pipeline
+        let apply_uniforms = self.attention_uniforms(anchor, batch_size, false, false);
+        let apply_pipeline = pipelines::attentionapply::Pipeline::new::<f32>(anchor, (
+                &apply_uniforms,
+                &attention,
+                &value_pipeline.output_buffer,
+            ),
+            model_size,
+            self.sequence_length,
+            batch_size,
+        );
+
+        //Run apply pipeline
+        apply_pipeline.run(encoder, model_size, self.sequence_length, batch_size);
+
+        //Create output pipeline
+        let output_pipeline = pipelines::multiplybytranspose::Pipeline::new::<f32>(anchor, (
+                &projection_uniforms,
+                layer_output,
+                &apply_pipeline.output_buffer,
+            ),
+            model_size,
+            model_size,
+            token_count,
+        );
+
+        //Run output pipeline
+        output_pipeline.run(encoder, model_size, model_size, token_count);
+
+        ForwardOutput {
+            query: query_pipeline.output_buffer,
+            key: key_pipeline.output_buffer,
+            value: value_pipeline.output_buffer,
+            attention,
+            heads: apply_pipeline.output_buffer,
+            output: output_pipeline.output_buffer,
+        }
+    }
+}
+
+#[typetag::serde]
+impl super::NetworkLayer for MultiHeadAttention {
+    fn get_topology(&self) -> Vec<(usize, usize)> {
+        let vec: Vec<(usize, usize)> = vec![(self.model_dimension, self.model_dimension), (self.model_dimension, self.model_dimension), (self.model_dimension, self.model_dimension), (self.model_dimension, self.model_dimension)];
+
+        //Return
+        vec
+    }
+
+    fn load_to_gpu(&self, anchor: &pipelines::Device,) -> Vec<wgpu::Buffer> {
+        let device = &anchor.device;
+        let mut vec: Vec<wgpu::Buffer> = Vec::with_capacity(4);
+
+        let layer_query: wgpu::Buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&self.query_weights[..]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+        vec.push(layer_query);
+
+        let layer_key: wgpu::Buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&self.key_weights[..]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+        vec.push(layer_key);
+
+        let layer_value: wgpu::Buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&self.value_weights[..]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+        vec.push(layer_value);
+
+        let layer_output: wgpu::Buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&self.output_weights[..]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+        vec.push(layer_output);
+
+        vec
+    }
+
+    fn save_from_gpu(&mut self, anchor: &pipelines::Device, data: &Vec<wgpu::Buffer>) {
+        let queue = &anchor.queue;
+        let device = &anchor.device;
+        let type_size = std::mem::size_of::<f32>();
+        let weight_count = self.model_dimension * self.model_dimension;
+
+        let mut gpu_data = data.iter();
+        let layer_query = gpu_data.next().unwrap();
+        let layer_key = gpu_data.next().unwrap();
+        let layer_value = gpu_data.next().unwrap();
+        let layer_output = gpu_data.next().unwrap();
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        //Copy to readable buffer
+        let layer_query_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Staging buffer"),
+                size: (type_size * weight_count) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }
+        );
+        encoder.copy_buffer_to_buffer(
+            layer_query, 0,
+            &layer_query_buffer, 0,
+            (type_size * weight_count) as wgpu::BufferAddress,
+        );
+
+        let layer_key_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Staging buffer"),
+                size: (type_size * weight_count) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }
+        );
+        encoder.copy_buffer_to_buffer(
+            layer_key, 0,
+            &layer_key_buffer, 0,
+            (type_size * weight_count) as wgpu::BufferAddress,
+        );
+
+        let layer_value_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Staging buffer"),
+                size: (type_size * weight_count) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }
+        );
+        encoder.copy_buffer_to_buffer(
+            layer_value, 0,
+            &layer_value_buffer, 0,
+            (type_size * weight_count) as wgpu::BufferAddress,
+        );
+
+        let layer_output_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Staging buffer"),
+                size: (type_size * weight_count) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }
+        );
+        encoder.copy_buffer_to_buffer(
+            layer_output, 0,
+            &layer_output_buffer, 0,
+            (type_size * weight_count) as wgpu::BufferAddress,
+        );
+
+        //Submit commands to gpu
+        queue.submit(Some(encoder.finish()));
+
+        //Create future of the data
+        let layer_query_slice = layer_query_buffer.slice(..);
+        let layer_query_future = layer_query_slice.map_async(wgpu::MapMode::Read);
+
+        let layer_key_slice = layer_key_buffer.slice(..);
+        let layer_key_future = layer_key_slice.map_async(wgpu::MapMode::Read);
+
+        let layer_value_slice = layer_value_buffer.slice(..);
+        let layer_value_future = layer_value_slice.map_async(wgpu::MapMode::Read);
+
+        let layer_output_slice = layer_output_buffer.slice(..);
+        let layer_output_future = layer_output_slice.map_async(wgpu::MapMode::Read);
+
+        //Register mapping callbacks
+        device.poll(wgpu::Maintain::Wait);
+
+        //Read from gpu
+        block_on(async {
+            match layer_query_future.await {
+                Ok(()) => {
+                    //Get buffer contents
+                    let data = layer_query_slice.get_mapped_range();
+                    //Convert to f32
+                    let result: Vec<f32> = data.chunks_exact(type_size).map(|b| *bytemuck::from_bytes::<f32>(b)).collect();
+                     //Drop mapped view
+                    drop(data);
+                    //Unmap buffer
+                    layer_query_buffer.unmap();
+
+                    //Save data
+                    self.query_weights = result;
+                }
+                Err(e) => {
+                    eprintln!("Failed to save layer_query to cpu: {}", e);
+                }
+            }
+            match layer_key_future.await {
+                Ok(()) => {
+                    //Get buffer contents
+                    let data = layer_key_slice.get_mapped_range();
+                    //Convert to f32
+                    let result: Vec<f32> = data.chunks_exact(type_size).map(|b| *bytemuck::from_bytes::<f32>(b)).collect();
+                     //Drop mapped view
+                    drop(data);
+                    //Unmap buffer
+                    layer_key_buffer.unmap();
+
+                    //Save data
+                    self.key_weights = result;
+                }
+                Err(e) => {
+                    eprintln!("Failed to save layer_key to cpu: {}", e);
+                }
+            }
+            match layer_value_future.await {
+                Ok(()) => {
+                    //Get buffer contents
+                    let data = layer_value_slice.get_mapped_range();
+                    //Convert to f32
+                    let result: Vec<f32> = data.chunks_exact(type_size).map(|b| *bytemuck::from_bytes::<f32>(b)).collect();
+                     //Drop mapped view
+                    drop(data);
+                    //Unmap buffer
+                    layer_value_buffer.unmap();
+
+                    //Save data
+                    self.value_weights = result;
+                }
+                Err(e) => {
+                    eprintln!("Failed to save layer_value to cpu: {}", e);
+                }
+            }
+            match layer_output_future.await {
+                Ok(()) => {
+                    //Get buffer contents
+                    let data = layer_output_slice.get_mapped_range();
+                    //Convert to f32
+                    let result: Vec<f32> = data.chunks_exact(type_size).map(|b| *bytemuck::from_bytes::<f32>(b)).collect();
+                     //Drop mapped view
+                    drop(data);
+                    //Unmap buffer
+                    layer_output_buffer.unmap();
+
+                    //Save data
+                    self.output_weights = result;
+                }
+                Err(e) => {
+                    eprintln!("Failed to save layer_output to cpu: {}", e);
+                }
+            }
+        });
+    }
+
+    fn forward(&self,
+               input: &wgpu::Buffer,
+               layer_data: &Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> wgpu::Buffer {
+        let attention_output = self.run_attention(input, layer_data, anchor, encoder, batch_size);
+
+        //Return
+        attention_output.output
+    }
+
+    fn forward_for_backprop(&self,
+               input: &wgpu::Buffer,
+               layer_data: &mut Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> (wgpu::Buffer, Vec<wgpu::Buffer>) {
+        let attention_output = self.run_attention(input, layer_data, anchor, encoder, batch_size);
+
+        //Create vec for return
+        let vec: Vec<wgpu::Buffer> = vec![attention_output.query, attention_output.key, attention_output.value, attention_output.attention, attention_output.heads];
+
+        //Return
+        (attention_output.output, vec)
+    }
+
+    fn backprop(&self,
+                backprop_grad: &wgpu::Buffer,
+                layer_data: &Vec<wgpu::Buffer>,
+                backprop_data: &Vec<wgpu::Buffer>,
+                anchor: &pipelines::Device,
+                encoder: &mut wgpu::CommandEncoder,
+                batch_size: usize,) -> (wgpu::Buffer, Vec<Option<wgpu::Buffer>>) {
+        let device = &anchor.device;
+        let model_size = self.model_dimension;
+        let token_count = self.sequence_length * batch_size;
+        let score_count = self.sequence_length * self.head_count * batch_size;
+
+        let mut gpu_data = layer_data.iter();
+        let layer_query = gpu_data.next().unwrap();
+        let layer_key = gpu_data.next().unwrap();
+        let layer_value = gpu_data.next().unwrap();
+        let layer_output = gpu_data.next().unwrap();
+
+        let mut gpu_data = backprop_data.iter();
+        let layer_queries = gpu_data.next().unwrap();
+        let layer_keys = gpu_data.next().unwrap();
+        let layer_values = gpu_data.next().unwrap();
+        let layer_attention = gpu_data.next().unwrap();
+        let layer_heads = gpu_data.next().unwrap();
+        let layer_input = gpu_data.next().unwrap();
+
+        //Create weight_grad uniforms
+        let weight_grad_uniforms = {
+            let uniform_data = [model_size as u32, token_count as u32, model_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        //Create output_grad pipelines
+        let output_weight_grad_pipeline = pipelines::multiplytransposewith::Pipeline::new::<f32>(anchor, (
+                &weight_grad_uniforms,
+                layer_heads,
+                backprop_grad,
+            ),
+            model_size,
+            token_count,
+            model_size,
+        );
+
+        let heads_grad_uniforms = {
+            let uniform_data = [model_size as u32, model_size as u32, token_count as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let heads_grad_pipeline = pipelines::projectiongrad::Pipeline::new::<f32>(anchor, (
+                &heads_grad_uniforms,
+                layer_output,
+                backprop_grad,
+            ),
+            model_size,
+            model_size,
+            token_count,
+        );
+
+        //Run output_grad pipelines
+        output_weight_grad_pipeline.run(encoder, model_size, token_count, model_size);
+        heads_grad_pipeline.run(encoder, model_size, model_size, token_count);
+
+        //Create attention_grad pipelines
+        let plain_uniforms = self.attention_uniforms(anchor, batch_size, false, false);
+        let scaled_uniforms = self.attention_uniforms(anchor, batch_size, false, true);
+
+        let attention_grad_pipeline = pipelines::attentionscores::Pipeline::new::<f32>(anchor, (
+                &plain_uniforms,
+                &heads_grad_pipeline.output_buffer,
+                layer_values,
+            ),
+            self.sequence_length,
+            self.head_count,
+            batch_size,
+        );
+
+        let value_grad_pipeline = pipelines::attentionvaluegrad::Pipeline::new::<f32>(anchor, (
+                &plain_uniforms,
+                layer_attention,
+                &heads_grad_pipeline.output_buffer,
+            ),
+            model_size,
+            self.sequence_length,
+            batch_size,
+        );
+
+        //Run attention_grad pipelines
+        attention_grad_pipeline.run(encoder, self.sequence_length, self.head_count, batch_size);
+        value_grad_pipeline.run(encoder, model_size, self.sequence_length, batch_size);
+
+        //Create score_grad pipeline
+        //Masked scores have zero attention so they get no gradient
+        let score_grad_uniforms = {
+            let uniform_data = [self.sequence_length as u32, score_count as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let score_grad_pipeline = pipelines::softmaxprime::Pipeline::new::<f32>(anchor, (
+                &score_grad_uniforms,
+                layer_attention,
+                &attention_grad_pipeline.output_buffer,
+            ),
+            self.sequence_length,
+            score_count,
+        );
+
+        //Run score_grad pipeline
+        score_grad_pipeline.run(encoder, self.sequence_length, score_count);
+
+        //Create query_grad and key_grad pipelines
+        let query_grad_pipeline = pipelines::attentionapply::Pipeline::new::<f32>(anchor, (
+                &scaled_uniforms,
+                &score_grad_pipeline.output_buffer,
+                layer_keys,
+            ),
+            model_size,
+            self.sequence_length,
+            batch_size,
+        );
+
+        let key_grad_pipeline = pipelines::attentionvaluegrad::Pipeline::new::<f32>(anchor, (
+                &scaled_uniforms,
+                &score_grad_pipeline.output_buffer,
+                layer_queries,
+            ),
+            model_size,
+            self.sequence_length,
+            batch_size,
+        );
+
+        //Run query_grad and key_grad pipelines
+        query_grad_pipeline.run(encoder, model_size, self.sequence_length, batch_size);
+        key_grad_pipeline.run(encoder, model_size, self.sequence_length, batch_size);
+
+        //Create projection weight_grad pipelines
+        let query_weight_grad_pipeline = pipelines::multiplytransposewith::Pipeline::new::<f32>(anchor, (
+                &weight_grad_uniforms,
+                layer_input,
+                &query_grad_pipeline.output_buffer,
+            ),
+            model_size,
+            token_count,
+            model_size,
+        );
+
+        let key_weight_grad_pipeline = pipelines::multiplytransposewith::Pipeline::new::<f32>(anchor, (
+                &weight_grad_uniforms,
+                layer_input,
+                &key_grad_pipeline.output_buffer,
+            ),
+            model_size,
+            token_count,
+            model_size,
+        );
+
+        let value_weight_grad_pipeline = pipelines::multiplytransposewith::Pipeline::new::<f32>(anchor, (
+                &weight_grad_uniforms,
+                layer_input,
+                &value_grad_pipeline.output_buffer,
+            ),
+            model_size,
+            token_count,
+            model_size,
+        );
+
+        //Run projection weight_grad pipelines
+        query_weight_grad_pipeline.run(encoder, model_size, token_count, model_size);
+        key_weight_grad_pipeline.run(encoder, model_size, token_count, model_size);
+        value_weight_grad_pipeline.run(encoder, model_size, token_count, model_size);
+
+        //Create input_grad pipeline
+        let input_grad_uniforms = {
+            let uniform_data = [model_size as u32, token_count as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let input_grad_pipeline = pipelines::attentioninputgrad::Pipeline::new::<f32>(anchor, (
+                &input_grad_uniforms,
+                layer_query,
+                layer_key,
+                layer_value,
+                &query_grad_pipeline.output_buffer,
+                &key_grad_pipeline.output_buffer,
+                &value_grad_pipeline.output_buffer,
+            ),
+            model_size,
+            token_count,
+        );
+
+        //Run input_grad pipeline
+        input_grad_pipeline.run(encoder, model_size, token_count);
+
+        //Return
+        let vec: Vec<Option<wgpu::Buffer>> = vec![Some(query_weight_grad_pipeline.output_buffer), Some(key_weight_grad_pipeline.output_buffer), Some(value_weight_grad_pipeline.output_buffer), Some(output_weight_grad_pipeline.output_buffer)];
+        (input_grad_pipeline.output_buffer, vec)
+    }
+}
@@ -16,6 +16,11 @@ pub struct Batchnorm {
 
 #[typetag::serde]
 impl super::NetworkLayer for Batchnorm {
+    fn trainable_parameters(&self) -> Vec<usize> {
+        //Running statistics are updated in the forward pass
+        vec![0, 1]
+    }
+
     fn get_topology(&self) -> Vec<(usize, usize)> {
         let mut vec: Vec<(usize, usize)> = Vec::with_capacity(5);
         vec.push((self.dimension, 1));
@@ -466,11 +471,12 @@ impl super::NetworkLayer for Batchnorm {
         mean_update_pipeline.run(encoder, self.dimension);
 
         //Create normalization pipeline
+        //Training normalizes with the batch statistics, the running ones are only for inference
         let normalization_pipeline = pipelines::batchnorm::Pipeline::new::<f32>(anchor, (
                 &mean_uniforms,
                 input,
-                &mean_update_pipeline.output_buffer,
-                &var_update_pipeline.output_buffer,
+                &mean_pipeline.output_buffer,
+                &variance_pipeline.output_buffer,
             ),
             self.dimension,
             batch_size,
@@ -479,19 +485,6 @@ impl super::NetworkLayer for Batchnorm {
         //Run normalization pipeline
         normalization_pipeline.run(encoder, self.dimension, batch_size);
 
-        //Create batchnormprime pipeline
-        let batchnormprime_pipeline = pipelines::batchnormprime::Pipeline::new::<f32>(anchor, (
-                &mean_uniforms,
-                layer_gamma,
-                &var_update_pipeline.output_buffer,
-            ),
-            self.dimension,
-            batch_size,
-        );
-
-        //Run batchnormprime pipeline
-        batchnormprime_pipeline.run(encoder, self.dimension, batch_size);
-        
         //Create scale pipeline
         let scale_pipeline = pipelines::scalebatchwithvector::Pipeline::new::<f32>(anchor, (
                 &mean_uniforms,
@@ -519,9 +512,7 @@ impl super::NetworkLayer for Batchnorm {
         bias_pipeline.run(encoder, self.dimension, batch_size);
 
         //Create vec for return
-        let mut vec: Vec<wgpu::Buffer> = Vec::with_capacity(3);
-        vec.push(normalization_pipeline.output_buffer);
-        vec.push(batchnormprime_pipeline.output_buffer);
+        let vec: Vec<wgpu::Buffer> = vec![normalization_pipeline.output_buffer, variance_pipeline.output_buffer];
         
         //Update mutable values
         *data_mean = mean_update_pipeline.output_buffer;
@@ -549,7 +540,7 @@ impl super::NetworkLayer for Batchnorm {
         let device = &anchor.device;
         
         let mut gpu_data = layer_data.into_iter();
-        let _layer_gamma = gpu_data.next().unwrap();
+        let layer_gamma = gpu_data.next().unwrap();
         let _layer_beta = gpu_data.next().unwrap();
         let _data_var =  gpu_data.next().unwrap();
         let _data_mean = gpu_data.next().unwrap();
@@ -557,7 +548,7 @@ impl super::NetworkLayer for Batchnorm {
 
         let mut gpu_data = backprop_data.into_iter();
         let layer_normed = gpu_data.next().unwrap();
-        let layer_normprime = gpu_data.next().unwrap();
+        let batch_var = gpu_data.next().unwrap();
         let _layer_input = gpu_data.next().unwrap();
 
         //Create beta_grad pipeline
@@ -583,10 +574,23 @@ impl super::NetworkLayer for Batchnorm {
         //Run beta_grad pipeline
         beta_grad_pipeline.run(encoder, self.dimension, batch_size);
 
+        //Create scaled_grad pipeline
+        let scaled_grad_pipeline = pipelines::elementmultiply::Pipeline::new::<f32>(anchor, (
+                &beta_grad_uniforms,
+                layer_normed,
+                backprop_grad,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run scaled_grad pipeline
+        scaled_grad_pipeline.run(encoder, self.dimension, batch_size);
+
         //Create gamma_grad pipeline
         let gamma_grad_pipeline = pipelines::batchtotal::Pipeline::new::<f32>(anchor, (
                 &beta_grad_uniforms,
-                layer_normed,
+                &scaled_grad_pipeline.output_buffer,
             ),
             self.dimension,
             batch_size,
@@ -596,10 +600,13 @@ impl super::NetworkLayer for Batchnorm {
         gamma_grad_pipeline.run(encoder, self.dimension, batch_size);
 
         //Create input_grad pipeline
-        let input_grad_pipeline = pipelines::elementmultiply::Pipeline::new::<f32>(anchor, (
+        //Includes the terms flowing through the batch mean and variance
+        let input_grad_pipeline = pipelines::batchnormgrad::Pipeline::new::<f32>(anchor, (
                 &beta_grad_uniforms,
-                layer_normprime,
                 backprop_grad,
+                layer_gamma,
+                layer_normed,
+                batch_var,
             ),
             self.dimension,
             batch_size,
@@ -618,3 +625,137 @@ impl super::NetworkLayer for Batchnorm {
         (input_grad_pipeline.output_buffer, vec)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::layers::NetworkLayer;
+    use crate::network::metrics::read_buffer;
+
+    //Cpu formulas of the batch normalization forward pass and its input gradient, in f64
+    //The formula tests don't run the kernels, only backprop_matches_finite_differences does and it needs a gpu
+    //Matrices are column major, rows are features and columns are batch items
+    const EPSILON: f64 = 0.00000001;
+
+    fn batch_statistics(input: &[f64], rows: usize, columns: usize) -> (Vec<f64>, Vec<f64>) {
+        let mean: Vec<f64> = (0..rows).map(|row| (0..columns).map(|column| input[column * rows + row]).sum::<f64>() / columns as f64).collect();
+        let var: Vec<f64> = (0..rows).map(|row| (0..columns).map(|column| (input[column * rows + row] - mean[row]).powi(2)).sum::<f64>() / columns as f64).collect();
+        (mean, var)
+    }
+
+    fn forward(input: &[f64], gamma: &[f64], beta: &[f64], rows: usize, columns: usize) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
+        let (mean, var) = batch_statistics(input, rows, columns);
+        let normed: Vec<f64> = (0..rows * columns).map(|index| (input[index] - mean[index % rows]) / (var[index % rows] + EPSILON).sqrt()).collect();
+        let output = (0..rows * columns).map(|index| gamma[index % rows] * normed[index] + beta[index % rows]).collect();
+        (output, normed, var)
+    }
+
+    fn input_grad(grad: &[f64], gamma: &[f64], normed: &[f64], var: &[f64], rows: usize, columns: usize) -> Vec<f64> {
+        (0..rows * columns).map(|index| {
+            let row = index % rows;
+            let grad_mean = (0..columns).map(|k| grad[k * rows + row]).sum::<f64>() / columns as f64;
+            let grad_normed_mean = (0..columns).map(|k| grad[k * rows + row] * normed[k * rows + row]).sum::<f64>() / columns as f64;
+            let scale = gamma[row] / (var[row] + EPSILON).sqrt();
+            scale * (grad[index] - grad_mean - normed[index] * grad_normed_mean)
+        }).collect()
+    }
+
+    //Central differences of the loss sum(grad * output), whose gradient with respect to the output is grad
+    fn numeric_grad(input: &[f64], gamma: &[f64], beta: &[f64], grad: &[f64], rows: usize, columns: usize) -> Vec<f64> {
+        let loss = |input: &[f64]| -> f64 {
+            forward(input, gamma, beta, rows, columns).0.iter().zip(grad.iter()).map(|(output, grad)| output * grad).sum()
+        };
+        let h = 0.00001;
+        (0..rows * columns).map(|index| {
+            let mut plus = input.to_vec();
+            plus[index] += h;
+            let mut minus = input.to_vec();
+            minus[index] -= h;
+            (loss(&plus) - loss(&minus)) / (2.0 * h)
+        }).collect()
+    }
+
+    const ROWS: usize = 3;
+    const COLUMNS: usize = 5;
+
+    fn test_input() -> Vec<f64> {
+        (0..ROWS * COLUMNS).map(|index| ((index * 7 + 3) % 11) as f64 * 0.37 - 1.5).collect()
+    }
+
+    fn test_grad() -> Vec<f64> {
+        (0..ROWS * COLUMNS).map(|index| ((index * 5 + 1) % 9) as f64 * 0.25 - 1.0).collect()
+    }
+
+    const GAMMA: [f64; ROWS] = [0.8, -1.3, 2.1];
+    const BETA: [f64; ROWS] = [0.1, 0.0, -0.4];
+
+    #[test]
+    fn input_grad_formula_matches_finite_differences() {
+        let (input, grad) = (test_input(), test_grad());
+        let (_, normed, var) = forward(&input, &GAMMA, &BETA, ROWS, COLUMNS);
+        let analytic = input_grad(&grad, &GAMMA, &normed, &var, ROWS, COLUMNS);
+        let numeric = numeric_grad(&input, &GAMMA, &BETA, &grad, ROWS, COLUMNS);
+        for index in 0..ROWS * COLUMNS {
+            assert!((numeric[index] - analytic[index]).abs() < 0.000001 * numeric[index].abs().max(1.0),
+                    "Input {}: numeric gradient {} but the formula gives {}", index, numeric[index], analytic[index]);
+        }
+    }
+
+    #[test]
+    fn input_grad_formula_sums_to_zero_per_feature() {
+        //The output does not change when every item of a feature is shifted by the same amount
+        let (rows, columns) = (2, 4);
+        let input = vec![0.3, -1.0, 1.2, 0.5, -0.7, 2.0, 0.1, -0.2];
+        let gamma = vec![1.5, 0.5];
+        let grad = vec![0.2, -0.1, 0.9, 0.4, -0.6, 0.3, 0.05, -1.0];
+        let (_, normed, var) = forward(&input, &gamma, &[0.0, 0.0], rows, columns);
+        let analytic = input_grad(&grad, &gamma, &normed, &var, rows, columns);
+        for row in 0..rows {
+            let total: f64 = (0..columns).map(|column| analytic[column * rows + row]).sum();
+            assert!(total.abs() < 0.000000001, "Feature {} gradients sum to {}", row, total);
+        }
+    }
+
+    fn storage_buffer(anchor: &pipelines::Device, data: &[f32]) -> wgpu::Buffer {
+        anchor.device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(data),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        )
+    }
+
+    //Runs Batchnorm::forward_for_backprop and Batchnorm::backprop, so the batchnormgrad kernel, against finite differences
+    #[test]
+    #[ignore = "needs a gpu adapter"]
+    fn backprop_matches_finite_differences() {
+        let anchor = block_on(pipelines::Device::new());
+        let (input, grad) = (test_input(), test_grad());
+        let layer = Batchnorm {
+            gamma: GAMMA.iter().map(|&gamma| gamma as f32).collect(),
+            beta: BETA.iter().map(|&beta| beta as f32).collect(),
+            data_var: vec![1.0; ROWS],
+            data_mean: vec![0.0; ROWS],
+            batches_sampled: 0,
+            dimension: ROWS,
+        };
+        let mut layer_data = layer.load_to_gpu(&anchor);
+        let input_buffer = storage_buffer(&anchor, &input.iter().map(|&value| value as f32).collect::<Vec<f32>>());
+        let grad_buffer = storage_buffer(&anchor, &grad.iter().map(|&value| value as f32).collect::<Vec<f32>>());
+
+        let mut encoder = anchor.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        let (_, mut backprop_data) = layer.forward_for_backprop(&input_buffer, &mut layer_data, &anchor, &mut encoder, COLUMNS);
+        //The network appends the layer input to its backprop data
+        backprop_data.push(input_buffer);
+        let (input_grad_buffer, _) = layer.backprop(&grad_buffer, &layer_data, &backprop_data, &anchor, &mut encoder, COLUMNS);
+        anchor.queue.submit(Some(encoder.finish()));
+
+        let kernel = read_buffer(&input_grad_buffer, &anchor, ROWS * COLUMNS).unwrap();
+        let numeric = numeric_grad(&input, &GAMMA, &BETA, &grad, ROWS, COLUMNS);
+        for index in 0..ROWS * COLUMNS {
+            assert!((numeric[index] - kernel[index] as f64).abs() < 0.001 * numeric[index].abs().max(1.0),
+                    "Input {}: numeric gradient {} but Batchnorm::backprop gives {}", index, numeric[index], kernel[index]);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use crate::pipelines;
+
+use serde::{Serialize, Deserialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+//Function and derivative are GLSL expressions of x compiled into the kernels
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Elementwise {
+    pub function: String,
+    pub derivative: String,
+    pub dimension: usize,
+}
+
+#[typetag::serde]
+impl super::NetworkLayer for Elementwise {
+    fn get_topology(&self) -> Vec<(usize, usize)> {
+        #[allow(unused_mut)]
+        let mut vec: Vec<(usize, usize)> = Vec::with_capacity(0);
+
+        vec
+    }
+
+    fn load_to_gpu(&self, anchor: &pipelines::Device,) -> Vec<wgpu::Buffer> {
+        let _device = &anchor.device;
+        #[allow(unused_mut)]
+        let mut vec: Vec<wgpu::Buffer> = Vec::with_capacity(0);
+
+        vec
+    }
+
+    fn save_from_gpu(&mut self, _anchor: &pipelines::Device, _data: &Vec<wgpu::Buffer>) {
+        //Nothing to do
+    }
+
+    fn forward(&self, 
+               input: &wgpu::Buffer,
+               layer_data: &Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> wgpu::Buffer {
+        let device = &anchor.device;
+        
+        let mut _gpu_data = layer_data.iter();
+
+        //Create activation pipeline
+        let activation_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32,];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+        
+        let activation_pipeline = pipelines::elementwise::Pipeline::new::<f32>(anchor, (
+                &activation_uniforms,
+                input,
+            ),
+            &self.function,
+            self.dimension,
+            batch_size,
+        );
+
+        //Run activation pipeline
+        activation_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Return
+        activation_pipeline.output_buffer
+    }
+
+    fn forward_for_backprop(&self, 
+               input: &wgpu::Buffer,
+               layer_data: &mut Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> (wgpu::Buffer, Vec<wgpu::Buffer>) {
+        let device = &anchor.device;
+        
+        let mut _gpu_data = layer_data.iter_mut();
+
+        //Create activation pipeline
+        let activation_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32,];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+        
+        let activation_pipeline = pipelines::elementwise::Pipeline::new::<f32>(anchor, (
+                &activation_uniforms,
+                input,
+            ),
+            &self.function,
+            self.dimension,
+            batch_size,
+        );
+
+        //Run activation pipeline
+        activation_pipeline.run(encoder, self.dimension, batch_size);
+        
+        //Create activationprime pipeline
+        let activationprime_pipeline = pipelines::elementwise::Pipeline::new::<f32>(anchor, (
+                &activation_uniforms,
+                input,
+            ),
+            &self.derivative,
+            self.dimension,
+            batch_size,
+        );
+
+        //Run activationprime pipeline
+        activationprime_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create vec for return
+        let vec: Vec<wgpu::Buffer> = vec![activationprime_pipeline.output_buffer];
+
+        //Return
+        (activation_pipeline.output_buffer, vec)
+    }
+
+    fn backprop(&self,
+                backprop_grad: &wgpu::Buffer,
+                layer_data: &Vec<wgpu::Buffer>, 
+                backprop_data: &Vec<wgpu::Buffer>,
+                anchor: &pipelines::Device,
+                encoder: &mut wgpu::CommandEncoder,
+                batch_size: usize,) -> (wgpu::Buffer, Vec<Option<wgpu::Buffer>>) {
+        let device = &anchor.device;
+        
+        let mut _gpu_data = layer_data.iter();
+
+        let mut gpu_data = backprop_data.iter();
+        let layer_outputprime = gpu_data.next().unwrap();
+        let _layer_input = gpu_data.next().unwrap();
+
+        //Create input_grad pipeline
+        let input_grad_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let input_grad_pipeline = pipelines::elementmultiply::Pipeline::new::<f32>(anchor, (
+                &input_grad_uniforms,
+                layer_outputprime,
+                backprop_grad,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run input_grad pipeline
+        input_grad_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Return
+        let vec: Vec<Option<wgpu::Buffer>> = vec![None];
+        (input_grad_pipeline.output_buffer, vec)
+    }
+}
+
@@ -0,0 +1,264 @@
+use crate::pipelines;
+
+use serde::{Serialize, Deserialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use futures::executor::block_on;
+
+//Takes u32 token indices as input rather than floats
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Embedding {
+    pub table: Vec<f32>,
+    pub vocab_size: usize,
+    pub embedding_size: usize,
+    pub token_count: usize,
+    pub padding_index: Option<u32>,
+}
+
+impl Embedding {
+    fn uniform_data(&self, batch_size: usize) -> [u32; 6] {
+        let (padding_index, use_padding) = match self.padding_index {
+            Some(index) => (index, 1),
+            None => (0, 0),
+        };
+        [(self.token_count * self.embedding_size) as u32, batch_size as u32, self.embedding_size as u32, padding_index, use_padding, self.vocab_size as u32]
+    }
+}
+
+#[typetag::serde]
+impl super::NetworkLayer for Embedding {
+    fn get_topology(&self) -> Vec<(usize, usize)> {
+        let vec: Vec<(usize, usize)> = vec![(self.embedding_size, self.vocab_size)];
+
+        //Return
+        vec
+    }
+
+    fn load_to_gpu(&self, anchor: &pipelines::Device,) -> Vec<wgpu::Buffer> {
+        let device = &anchor.device;
+        let mut vec: Vec<wgpu::Buffer> = Vec::with_capacity(1);
+
+        let layer_table: wgpu::Buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&self.table[..]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+        vec.push(layer_table);
+
+        vec
+    }
+
+    fn save_from_gpu(&mut self, anchor: &pipelines::Device, data: &Vec<wgpu::Buffer>) {
+        let queue = &anchor.queue;
+        let device = &anchor.device;
+        let type_size = std::mem::size_of::<f32>();
+
+        let mut gpu_data = data.iter();
+        let layer_table = gpu_data.next().unwrap();
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        //Copy to readable buffer
+        let layer_table_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Staging buffer"),
+                size: (type_size * self.vocab_size * self.embedding_size) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }
+        );
+        encoder.copy_buffer_to_buffer(
+            layer_table, 0,
+            &layer_table_buffer, 0,
+            (type_size * self.vocab_size * self.embedding_size) as wgpu::BufferAddress,
+        );
+
+        //Submit commands to gpu
+        queue.submit(Some(encoder.finish()));
+
+        //Create future of the computation
+        let layer_table_slice = layer_table_buffer.slice(..);
+        let layer_table_future = layer_table_slice.map_async(wgpu::MapMode::Read);
+
+        //Register mapping callbacks
+        device.poll(wgpu::Maintain::Wait);
+
+        //Read from gpu
+        block_on(async {
+            match layer_table_future.await {
+                Ok(()) => {
+                    //Get buffer contents
+                    let data = layer_table_slice.get_mapped_range();
+                    //Convert to f32
+                    let result: Vec<f32> = data.chunks_exact(type_size).map(|b| *bytemuck::from_bytes::<f32>(b)).collect();
+                     //Drop mapped view
+                    drop(data);
+                    //Unmap buffer
+                    layer_table_buffer.unmap();
+
+                    //Save data
+                    self.table = result;
+                }
+                Err(e) => {
+                    eprintln!("Failed to save layer_table to cpu: {}", e);
+                }
+            }
+        });
+    }
+
+    fn forward(&self,
+               input: &wgpu::Buffer,
+               layer_data: &Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> wgpu::Buffer {
+        let device = &anchor.device;
+        let output_size = self.token_count * self.embedding_size;
+
+        let mut gpu_data = layer_data.iter();
+        let layer_table = gpu_data.next().unwrap();
+
+        //Create lookup pipeline
+        let lookup_uniforms = {
+            let uniform_data = self.uniform_data(batch_size);
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let lookup_pipeline = pipelines::embeddinglookup::Pipeline::new::<f32>(anchor, (
+                &lookup_uniforms,
+                input,
+                layer_table,
+            ),
+            output_size,
+            batch_size,
+        );
+
+        //Run lookup pipeline
+        lookup_pipeline.run(encoder, output_size, batch_size);
+
+        //Return
+        lookup_pipeline.output_buffer
+    }
+
+    fn forward_for_backprop(&self,
+               input: &wgpu::Buffer,
+               layer_data: &mut Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> (wgpu::Buffer, Vec<wgpu::Buffer>) {
+        let output = self.forward(input, layer_data, anchor, encoder, batch_size);
+
+        //Create vec for return
+        #[allow(unused_mut)]
+        let mut vec: Vec<wgpu::Buffer> = Vec::with_capacity(1);
+
+        //Return
+        (output, vec)
+    }
+
+    fn backprop(&self,
+                backprop_grad: &wgpu::Buffer,
+                layer_data: &Vec<wgpu::Buffer>,
+                backprop_data: &Vec<wgpu::Buffer>,
+                anchor: &pipelines::Device,
+                encoder: &mut wgpu::CommandEncoder,
+                batch_size: usize,) -> (wgpu::Buffer, Vec<Option<wgpu::Buffer>>) {
+        let device = &anchor.device;
+
+        let mut gpu_data = layer_data.iter();
+        let _layer_table = gpu_data.next().unwrap();
+
+        let mut gpu_data = backprop_data.iter();
+        let layer_input = gpu_data.next().unwrap();
+
+        //Create table_grad pipeline
+        let table_grad_uniforms = {
+            let uniform_data = self.uniform_data(batch_size);
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let table_grad_pipeline = pipelines::embeddinggrad::Pipeline::new::<f32>(anchor, (
+                &table_grad_uniforms,
+                layer_input,
+                backprop_grad,
+            ),
+            self.vocab_size,
+            self.embedding_size,
+        );
+
+        //Run table_grad pipeline
+        table_grad_pipeline.run(encoder, self.vocab_size, self.embedding_size);
+
+        //Indices are not differentiable so pass back zeros
+        let input_grad = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Output buffer"),
+                size: (std::mem::size_of::<f32>() * self.token_count * batch_size) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }
+        );
+
+        //Return
+        let vec: Vec<Option<wgpu::Buffer>> = vec![Some(table_grad_pipeline.output_buffer)];
+        (input_grad, vec)
+    }
+
+    fn check_input(&self, input: &[u8]) {
+        //The lookup kernel can't report a bad index, so catch it before upload
+        let tokens = input.chunks_exact(std::mem::size_of::<u32>()).map(|bytes| u32::from_ne_bytes(bytes.try_into().unwrap()));
+        if let Some(token) = tokens.filter(|&token| Some(token) != self.padding_index).find(|&token| token as usize >= self.vocab_size) {
+            panic!("Token {} is out of range for a vocabulary of {}", token, self.vocab_size);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::layers::NetworkLayer;
+
+    fn layer(padding_index: Option<u32>) -> Embedding {
+        Embedding {
+            table: vec![0.0; 4 * 2],
+            vocab_size: 4,
+            embedding_size: 2,
+            token_count: 3,
+            padding_index,
+        }
+    }
+
+    #[test]
+    fn tokens_inside_the_vocabulary_pass() {
+        layer(None).check_input(bytemuck::cast_slice(&[0u32, 3, 1]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Token 4 is out of range for a vocabulary of 4")]
+    fn token_outside_the_vocabulary_panics() {
+        layer(None).check_input(bytemuck::cast_slice(&[0u32, 4, 1]));
+    }
+
+    #[test]
+    fn padding_index_may_sit_outside_the_vocabulary() {
+        layer(Some(9)).check_input(bytemuck::cast_slice(&[9u32, 3, 9]));
+    }
+}
@@ -0,0 +1,420 @@
+use crate::pipelines;
+
+use serde::{Serialize, Deserialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use futures::executor::block_on;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LayerNorm {
+    pub gamma: Vec<f32>,
+    pub beta: Vec<f32>,
+    pub dimension: usize,
+}
+
+#[typetag::serde]
+impl super::NetworkLayer for LayerNorm {
+    fn get_topology(&self) -> Vec<(usize, usize)> {
+        let vec: Vec<(usize, usize)> = vec![(self.dimension, 1), (self.dimension, 1)];
+
+        //Return
+        vec
+    }
+
+    fn load_to_gpu(&self, anchor: &pipelines::Device,) -> Vec<wgpu::Buffer> {
+        let device = &anchor.device;
+        let mut vec: Vec<wgpu::Buffer> = Vec::with_capacity(2);
+
+        let layer_gamma: wgpu::Buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&self.gamma[..]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+        vec.push(layer_gamma);
+
+        let layer_beta: wgpu::Buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&self.beta[..]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+        vec.push(layer_beta);
+
+        vec
+    }
+
+    fn save_from_gpu(&mut self, anchor: &pipelines::Device, data: &Vec<wgpu::Buffer>) {
+        let queue = &anchor.queue;
+        let device = &anchor.device;
+        let type_size = std::mem::size_of::<f32>();
+
+        let mut gpu_data = data.iter();
+        let layer_gamma = gpu_data.next().unwrap();
+        let layer_beta = gpu_data.next().unwrap();
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        //Copy to readable buffer
+        let layer_gamma_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Staging buffer"),
+                size: (type_size * self.dimension) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }
+        );
+        encoder.copy_buffer_to_buffer(
+            layer_gamma, 0,
+            &layer_gamma_buffer, 0,
+            (type_size * self.dimension) as wgpu::BufferAddress,
+        );
+
+        let layer_beta_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Staging buffer"),
+                size: (type_size * self.dimension) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }
+        );
+        encoder.copy_buffer_to_buffer(
+            layer_beta, 0,
+            &layer_beta_buffer, 0,
+            (type_size * self.dimension) as wgpu::BufferAddress,
+        );
+
+        //Submit commands to gpu
+        queue.submit(Some(encoder.finish()));
+
+        //Create future of the data
+        let layer_gamma_slice = layer_gamma_buffer.slice(..);
+        let layer_gamma_future = layer_gamma_slice.map_async(wgpu::MapMode::Read);
+
+        let layer_beta_slice = layer_beta_buffer.slice(..);
+        let layer_beta_future = layer_beta_slice.map_async(wgpu::MapMode::Read);
+
+        //Register mapping callbacks
+        device.poll(wgpu::Maintain::Wait);
+
+        //Read from gpu
+        block_on(async {
+            match layer_gamma_future.await {
+                Ok(()) => {
+                    //Get buffer contents
+                    let data = layer_gamma_slice.get_mapped_range();
+                    //Convert to f32
+                    let result: Vec<f32> = data.chunks_exact(type_size).map(|b| *bytemuck::from_bytes::<f32>(b)).collect();
+                     //Drop mapped view
+                    drop(data);
+                    //Unmap buffer
+                    layer_gamma_buffer.unmap();
+
+                    //Save data
+                    self.gamma = result;
+                }
+                Err(e) => {
+                    eprintln!("Failed to save layer_gamma to cpu: {}", e);
+                }
+            }
+        });
+
+        block_on(async {
+            match layer_beta_future.await {
+                Ok(()) => {
+                    //Get buffer contents
+                    let data = layer_beta_slice.get_mapped_range();
+                    //Convert to f32
+                    let result: Vec<f32> = data.chunks_exact(type_size).map(|b| *bytemuck::from_bytes::<f32>(b)).collect();
+                     //Drop mapped view
+                    drop(data);
+                    //Unmap buffer
+                    layer_beta_buffer.unmap();
+
+                    //Save data
+                    self.beta = result;
+                }
+                Err(e) => {
+                    eprintln!("Failed to save layer_beta to cpu: {}", e);
+                }
+            }
+        });
+    }
+
+    fn forward(&self,
+               input: &wgpu::Buffer,
+               layer_data: &Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> wgpu::Buffer {
+        let device = &anchor.device;
+
+        let mut gpu_data = layer_data.iter();
+        let layer_gamma = gpu_data.next().unwrap();
+        let layer_beta = gpu_data.next().unwrap();
+
+        //Create mean pipeline
+        let normalization_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let mean_pipeline = pipelines::layermean::Pipeline::new::<f32>(anchor, (
+                &normalization_uniforms,
+                input,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run mean pipeline
+        mean_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create variance pipeline
+        let variance_pipeline = pipelines::layervar::Pipeline::new::<f32>(anchor, (
+                &normalization_uniforms,
+                input,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run variance pipeline
+        variance_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create normalization pipeline
+        let normalization_pipeline = pipelines::layernorm::Pipeline::new::<f32>(anchor, (
+                &normalization_uniforms,
+                input,
+                &mean_pipeline.output_buffer,
+                &variance_pipeline.output_buffer,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run normalization pipeline
+        normalization_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create scale pipeline
+        let scale_pipeline = pipelines::scalebatchwithvector::Pipeline::new::<f32>(anchor, (
+                &normalization_uniforms,
+                &normalization_pipeline.output_buffer,
+                layer_gamma,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run scale pipeline
+        scale_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create bias pipeline
+        let bias_pipeline = pipelines::addvectortobatch::Pipeline::new::<f32>(anchor, (
+                &normalization_uniforms,
+                &scale_pipeline.output_buffer,
+                layer_beta,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run bias pipeline
+        bias_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Return
+        bias_pipeline.output_buffer
+    }
+
+    fn forward_for_backprop(&self,
+               input: &wgpu::Buffer,
+               layer_data: &mut Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> (wgpu::Buffer, Vec<wgpu::Buffer>) {
+        let device = &anchor.device;
+
+        let mut gpu_data = layer_data.iter_mut();
+        let layer_gamma = gpu_data.next().unwrap();
+        let layer_beta = gpu_data.next().unwrap();
+
+        //Create mean pipeline
+        let normalization_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let mean_pipeline = pipelines::layermean::Pipeline::new::<f32>(anchor, (
+                &normalization_uniforms,
+                input,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run mean pipeline
+        mean_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create variance pipeline
+        let variance_pipeline = pipelines::layervar::Pipeline::new::<f32>(anchor, (
+                &normalization_uniforms,
+                input,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run variance pipeline
+        variance_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create normalization pipeline
+        let normalization_pipeline = pipelines::layernorm::Pipeline::new::<f32>(anchor, (
+                &normalization_uniforms,
+                input,
+                &mean_pipeline.output_buffer,
+                &variance_pipeline.output_buffer,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run normalization pipeline
+        normalization_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create scale pipeline
+        let scale_pipeline = pipelines::scalebatchwithvector::Pipeline::new::<f32>(anchor, (
+                &normalization_uniforms,
+                &normalization_pipeline.output_buffer,
+                layer_gamma,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run scale pipeline
+        scale_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create bias pipeline
+        let bias_pipeline = pipelines::addvectortobatch::Pipeline::new::<f32>(anchor, (
+                &normalization_uniforms,
+                &scale_pipeline.output_buffer,
+                layer_beta,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run bias pipeline
+        bias_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create vec for return
+        let vec: Vec<wgpu::Buffer> = vec![normalization_pipeline.output_buffer, variance_pipeline.output_buffer];
+
+        //Return
+        (bias_pipeline.output_buffer, vec)
+    }
+
+    fn backprop(&self,
+                backprop_grad: &wgpu::Buffer,
+                layer_data: &Vec<wgpu::Buffer>,
+                backprop_data: &Vec<wgpu::Buffer>,
+                anchor: &pipelines::Device,
+                encoder: &mut wgpu::CommandEncoder,
+                batch_size: usize,) -> (wgpu::Buffer, Vec<Option<wgpu::Buffer>>) {
+        let device = &anchor.device;
+
+        let mut gpu_data = layer_data.iter();
+        let layer_gamma = gpu_data.next().unwrap();
+        let _layer_beta = gpu_data.next().unwrap();
+
+        let mut gpu_data = backprop_data.iter();
+        let layer_normed = gpu_data.next().unwrap();
+        let layer_var = gpu_data.next().unwrap();
+        let _layer_input = gpu_data.next().unwrap();
+
+        //Create beta_grad pipeline
+        let beta_grad_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let beta_grad_pipeline = pipelines::batchtotal::Pipeline::new::<f32>(anchor, (
+                &beta_grad_uniforms,
+                backprop_grad,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run beta_grad pipeline
+        beta_grad_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create scaled_grad pipeline
+        let scaled_grad_pipeline = pipelines::elementmultiply::Pipeline::new::<f32>(anchor, (
+                &beta_grad_uniforms,
+                layer_normed,
+                backprop_grad,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run scaled_grad pipeline
+        scaled_grad_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create gamma_grad pipeline
+        let gamma_grad_pipeline = pipelines::batchtotal::Pipeline::new::<f32>(anchor, (
+                &beta_grad_uniforms,
+                &scaled_grad_pipeline.output_buffer,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run gamma_grad pipeline
+        gamma_grad_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create input_grad pipeline
+        let input_grad_pipeline = pipelines::layernormprime::Pipeline::new::<f32>(anchor, (
+                &beta_grad_uniforms,
+                backprop_grad,
+                layer_gamma,
+                layer_normed,
+                layer_var,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run input_grad pipeline
+        input_grad_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Return
+        let vec: Vec<Option<wgpu::Buffer>> = vec![Some(gamma_grad_pipeline.output_buffer), Some(beta_grad_pipeline.output_buffer)];
+        (input_grad_pipeline.output_buffer, vec)
+    }
+}
@@ -3,9 +3,17 @@ use crate::pipelines;
 use rand::prelude::*;
 
 pub mod fullyconnected;
+pub mod embedding;
 pub mod batchnorm;
+pub mod layernorm;
 pub mod relu;
+pub mod prelu;
 pub mod softmax;
+pub mod recurrent;
+pub mod attention;
+pub mod reshape;
+pub mod transpose;
+pub mod elementwise;
 
 #[typetag::serde(tag = "type")]
 pub trait NetworkLayer {
@@ -36,13 +44,52 @@ pub trait NetworkLayer {
                 anchor: &pipelines::Device,
                 encoder: &mut wgpu::CommandEncoder,
                 batch_size: usize,) -> (wgpu::Buffer, Vec<Option<wgpu::Buffer>>);
+
+    //Indices into layer_data of the buffers backprop trains, these are the ones regularisation penalises
+    fn trainable_parameters(&self) -> Vec<usize> {
+        (0..self.get_topology().len()).collect()
+    }
+
+    //True when the output is already a probability distribution rather than logits
+    fn outputs_probabilities(&self) -> bool {
+        false
+    }
+
+    //Inference that advances carried state, only stateful layers differ from forward
+    fn forward_stateful(&self,
+               input: &wgpu::Buffer,
+               layer_data: &mut Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> wgpu::Buffer {
+        self.forward(input, layer_data, anchor, encoder, batch_size)
+    }
+
+    //Returns carried state to zero
+    fn reset_state(&self, _anchor: &pipelines::Device, _layer_data: &mut Vec<wgpu::Buffer>) {
+    }
+
+    //Called on the raw input before it is uploaded, layers that index with their input check it here
+    fn check_input(&self, _input: &[u8]) {
+    }
+}
+
+//Errors describe why the layer can't take the input shape
+//Builds layers defined outside this crate, their NetworkLayer impl needs #[typetag::serde] to be saved and loaded
+pub trait LayerGenerator: std::fmt::Debug {
+    fn generate(&self, input_shape: &super::Shape) -> Result<(super::Shape, Box<dyn NetworkLayer>), String>;
 }
 
-pub fn generate_layer(input_size: usize, layer_type: super::LayerType) -> (usize, Box<dyn NetworkLayer>) {
+pub fn generate_layer(input_shape: &super::Shape, layer_type: super::LayerType) -> Result<(super::Shape, Box<dyn NetworkLayer>), String> {
     use super::LayerType::*;
+    use super::Shape;
     use rand_distr::*;
+    let input_size = input_shape.size();
     match layer_type {
         FullyConnected(output_size) => {
+            if input_shape.rank() != 1 {
+                return Err(format!("expected a flat input but got {}, add a Flatten layer first", input_shape));
+            }
             let mut rng = rand::thread_rng();
             let avg: f32 = ((input_size + output_size) as f32) / 2.0;
             let dist = Normal::new(0.0, 1.0 / avg.sqrt()).unwrap();
@@ -56,7 +103,47 @@ pub fn generate_layer(input_size: usize, layer_type: super::LayerType) -> (usize
             });
             
             //Return
-            (output_size, layer)
+            Ok((Shape::from(output_size), layer))
+        },
+        Embedding(vocab_size, embedding_size) => {
+            let mut rng = rand::thread_rng();
+            let dist = Normal::new(0.0, 1.0).unwrap();
+            let layer = Box::new(embedding::Embedding {
+                table:{
+                    let vector: Vec<f32> = (0..vocab_size * embedding_size).map(|_i| {rng.sample(dist)}).collect();
+                    vector
+                },
+                vocab_size,
+                embedding_size,
+                token_count: input_size,
+                padding_index: None,
+            });
+
+            //Return
+            Ok((input_shape.with_trailing(embedding_size), layer))
+        },
+        PaddedEmbedding(vocab_size, embedding_size, padding_index) => {
+            let mut rng = rand::thread_rng();
+            let dist = Normal::new(0.0, 1.0).unwrap();
+            let layer = Box::new(embedding::Embedding {
+                table:{
+                    //Padding row is zeroed and never updated
+                    let vector: Vec<f32> = (0..vocab_size * embedding_size).map(|i| {
+                        match i / embedding_size == padding_index as usize {
+                            true => 0.0,
+                            false => rng.sample(dist),
+                        }
+                    }).collect();
+                    vector
+                },
+                vocab_size,
+                embedding_size,
+                token_count: input_size,
+                padding_index: Some(padding_index),
+            });
+
+            //Return
+            Ok((input_shape.with_trailing(embedding_size), layer))
         },
         Batchnorm => {
             let mut rng = rand::thread_rng();
@@ -84,7 +171,23 @@ pub fn generate_layer(input_size: usize, layer_type: super::LayerType) -> (usize
             });
 
             //Return
-            (input_size, layer)
+            Ok((input_shape.clone(), layer))
+        },
+        LayerNorm => {
+            let layer = Box::new(layernorm::LayerNorm {
+                gamma:{
+                    let vector: Vec<f32> = (0..input_size).map(|_i| {1.0}).collect();
+                    vector
+                },
+                beta:{
+                    let vector: Vec<f32> = (0..input_size).map(|_i| {0.0}).collect();
+                    vector
+                },
+                dimension: input_size,
+            });
+
+            //Return
+            Ok((input_shape.clone(), layer))
         },
         Relu => {
             let layer = Box::new(relu::Relu {
@@ -92,7 +195,19 @@ pub fn generate_layer(input_size: usize, layer_type: super::LayerType) -> (usize
             });
 
             //Return
-            (input_size, layer)
+            Ok((input_shape.clone(), layer))
+        },
+        Prelu => {
+            let layer = Box::new(prelu::Prelu {
+                slope:{
+                    let vector: Vec<f32> = (0..input_size).map(|_i| {0.25}).collect();
+                    vector
+                },
+                dimension: input_size,
+            });
+
+            //Return
+            Ok((input_shape.clone(), layer))
         },
         Softmax => {
             let layer = Box::new(softmax::Softmax {
@@ -100,7 +215,140 @@ pub fn generate_layer(input_size: usize, layer_type: super::LayerType) -> (usize
             });
             
             //Return
-            (input_size, layer)
+            Ok((input_shape.clone(), layer))
+        },
+        Rnn(recurrence) => generate_recurrent(input_shape, recurrent::RecurrentCell::Rnn, recurrence),
+        Lstm(recurrence) => generate_recurrent(input_shape, recurrent::RecurrentCell::Lstm, recurrence),
+        Gru(recurrence) => generate_recurrent(input_shape, recurrent::RecurrentCell::Gru, recurrence),
+        MultiHeadAttention(attention) => {
+            let model_size = sequence_features(input_shape, attention.sequence_length)?;
+            if model_size % attention.head_count != 0 {
+                return Err(format!("{} features per token don't divide between {} heads", model_size, attention.head_count));
+            }
+            let mut rng = rand::thread_rng();
+            let dist = Normal::new(0.0, 1.0 / (model_size as f32).sqrt()).unwrap();
+            let mut generate_weights = || {
+                let vector: Vec<f32> = (0..model_size * model_size).map(|_i| {rng.sample(dist)}).collect();
+                vector
+            };
+            let layer = Box::new(attention::MultiHeadAttention {
+                query_weights: generate_weights(),
+                key_weights: generate_weights(),
+                value_weights: generate_weights(),
+                output_weights: generate_weights(),
+                model_dimension: model_size,
+                head_count: attention.head_count,
+                sequence_length: attention.sequence_length,
+                causal: attention.causal,
+            });
+
+            //Return
+            Ok((input_shape.clone(), layer))
+        },
+        Flatten => {
+            let layer = Box::new(reshape::Reshape {
+                input_shape: input_shape.dims.clone(),
+                output_shape: vec![input_size],
+            });
+
+            //Return
+            Ok((Shape::from(input_size), layer))
+        },
+        Reshape(dims) => {
+            let output_shape = Shape::from(dims);
+            if output_shape.size() != input_size {
+                return Err(format!("can't reshape {} into {}", input_shape, output_shape));
+            }
+            let layer = Box::new(reshape::Reshape {
+                input_shape: input_shape.dims.clone(),
+                output_shape: output_shape.dims.clone(),
+            });
+
+            //Return
+            Ok((output_shape, layer))
+        },
+        Transpose(permutation) => {
+            let mut sorted = permutation.clone();
+            sorted.sort();
+            if sorted != (0..input_shape.rank()).collect::<Vec<usize>>() {
+                return Err(format!("{:?} is not a permutation of the axes of {}", permutation, input_shape));
+            }
+            if input_shape.rank() > transpose::MAX_RANK {
+                return Err(format!("can't transpose {}, at most {} axes are supported", input_shape, transpose::MAX_RANK));
+            }
+            let output_shape = Shape::from(permutation.iter().map(|&axis| input_shape.dims[axis]).collect::<Vec<usize>>());
+            let layer = Box::new(transpose::Transpose {
+                input_shape: input_shape.dims.clone(),
+                permutation,
+            });
+
+            //Return
+            Ok((output_shape, layer))
+        },
+        Elementwise(function, derivative) => {
+            //Catch typos here rather than on the first forward pass
+            for expression in [&function, &derivative] {
+                if let Err(e) = pipelines::elementwise::compile(expression) {
+                    return Err(format!("can't compile elementwise expression {}: {}", expression, e));
+                }
+            }
+            let layer = Box::new(elementwise::Elementwise {
+                function,
+                derivative,
+                dimension: input_size,
+            });
+
+            //Return
+            Ok((input_shape.clone(), layer))
         },
+        Custom(generator) => generator.generate(input_shape),
+    }
+}
+
+//Features per step of a sequence shaped [steps, features] or flat
+fn sequence_features(input_shape: &super::Shape, step_count: usize) -> Result<usize, String> {
+    match input_shape.dims[..] {
+        [steps, features] if steps == step_count => Ok(features),
+        [size] if size % step_count == 0 => Ok(size / step_count),
+        _ => Err(format!("can't split {} into {} steps", input_shape, step_count)),
+    }
+}
+
+fn generate_recurrent(input_shape: &super::Shape, cell: recurrent::RecurrentCell, recurrence: super::Recurrence) -> Result<(super::Shape, Box<dyn NetworkLayer>), String> {
+    use rand_distr::*;
+    let super::Recurrence { hidden_size, step_count, return_sequences, stateful } = recurrence;
+    let input_dimension = sequence_features(input_shape, step_count)?;
+    let gate_size = cell.gate_count() * hidden_size;
+    let weight_size = input_dimension + hidden_size + 1;
+
+    let mut rng = rand::thread_rng();
+    let bound = 1.0 / (hidden_size as f32).sqrt();
+    let dist = Uniform::new(-bound, bound);
+    let layer = Box::new(recurrent::Recurrent {
+        cell,
+        weights:{
+            let vector: Vec<f32> = (0..gate_size * weight_size).map(|i| {
+                let (gate_row, column) = (i / weight_size, i % weight_size);
+                //Lstm forget gate bias starts at one so early gradients flow through the cell
+                match (cell, column == weight_size - 1, gate_row / hidden_size) {
+                    (recurrent::RecurrentCell::Lstm, true, 1) => 1.0,
+                    (_, true, _) => 0.0,
+                    _ => rng.sample(dist),
+                }
+            }).collect();
+            vector
+        },
+        input_dimension,
+        hidden_dimension: hidden_size,
+        step_count,
+        return_sequences,
+        stateful,
+        state: Vec::new(),
+    });
+
+    //Return
+    match return_sequences {
+        true => Ok((super::Shape::from(vec![step_count, hidden_size]), layer)),
+        false => Ok((super::Shape::from(hidden_size), layer)),
     }
 }
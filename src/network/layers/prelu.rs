@@ -0,0 +1,252 @@
+use crate::pipelines;
+
+use serde::{Serialize, Deserialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use futures::executor::block_on;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Prelu {
+    pub slope: Vec<f32>,
+    pub dimension: usize,
+}
+
+#[typetag::serde]
+impl super::NetworkLayer for Prelu {
+    fn get_topology(&self) -> Vec<(usize, usize)> {
+        let vec: Vec<(usize, usize)> = vec![(self.dimension, 1)];
+
+        //Return
+        vec
+    }
+
+    fn load_to_gpu(&self, anchor: &pipelines::Device,) -> Vec<wgpu::Buffer> {
+        let device = &anchor.device;
+        let mut vec: Vec<wgpu::Buffer> = Vec::with_capacity(1);
+
+        let layer_slope: wgpu::Buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&self.slope[..]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+        vec.push(layer_slope);
+
+        vec
+    }
+
+    fn save_from_gpu(&mut self, anchor: &pipelines::Device, data: &Vec<wgpu::Buffer>) {
+        let queue = &anchor.queue;
+        let device = &anchor.device;
+        let type_size = std::mem::size_of::<f32>();
+
+        let mut gpu_data = data.iter();
+        let layer_slope = gpu_data.next().unwrap();
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        //Copy to readable buffer
+        let layer_slope_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Staging buffer"),
+                size: (type_size * self.dimension) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }
+        );
+        encoder.copy_buffer_to_buffer(
+            layer_slope, 0,
+            &layer_slope_buffer, 0,
+            (type_size * self.dimension) as wgpu::BufferAddress,
+        );
+
+        //Submit commands to gpu
+        queue.submit(Some(encoder.finish()));
+
+        //Create future of the data
+        let layer_slope_slice = layer_slope_buffer.slice(..);
+        let layer_slope_future = layer_slope_slice.map_async(wgpu::MapMode::Read);
+
+        //Register mapping callbacks
+        device.poll(wgpu::Maintain::Wait);
+
+        //Read from gpu
+        block_on(async {
+            match layer_slope_future.await {
+                Ok(()) => {
+                    //Get buffer contents
+                    let data = layer_slope_slice.get_mapped_range();
+                    //Convert to f32
+                    let result: Vec<f32> = data.chunks_exact(type_size).map(|b| *bytemuck::from_bytes::<f32>(b)).collect();
+                     //Drop mapped view
+                    drop(data);
+                    //Unmap buffer
+                    layer_slope_buffer.unmap();
+
+                    //Save data
+                    self.slope = result;
+                }
+                Err(e) => {
+                    eprintln!("Failed to save layer_slope to cpu: {}", e);
+                }
+            }
+        });
+    }
+
+    fn forward(&self,
+               input: &wgpu::Buffer,
+               layer_data: &Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> wgpu::Buffer {
+        let device = &anchor.device;
+
+        let mut gpu_data = layer_data.iter();
+        let layer_slope = gpu_data.next().unwrap();
+
+        //Create activation pipeline
+        let activation_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32,];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let activation_pipeline = pipelines::prelu::Pipeline::new::<f32>(anchor, (
+                &activation_uniforms,
+                input,
+                layer_slope,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run activation pipeline
+        activation_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Return
+        activation_pipeline.output_buffer
+    }
+
+    fn forward_for_backprop(&self,
+               input: &wgpu::Buffer,
+               layer_data: &mut Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> (wgpu::Buffer, Vec<wgpu::Buffer>) {
+        let device = &anchor.device;
+
+        let mut gpu_data = layer_data.iter_mut();
+        let layer_slope = gpu_data.next().unwrap();
+
+        //Create activation pipeline
+        let activation_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32,];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let activation_pipeline = pipelines::prelu::Pipeline::new::<f32>(anchor, (
+                &activation_uniforms,
+                input,
+                layer_slope,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run activation pipeline
+        activation_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create activationprime pipeline
+        let activationprime_pipeline = pipelines::preluprime::Pipeline::new::<f32>(anchor, (
+                &activation_uniforms,
+                input,
+                layer_slope,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run activationprime pipeline
+        activationprime_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create vec for return
+        let vec: Vec<wgpu::Buffer> = vec![activationprime_pipeline.output_buffer];
+
+        //Return
+        (activation_pipeline.output_buffer, vec)
+    }
+
+    fn backprop(&self,
+                backprop_grad: &wgpu::Buffer,
+                layer_data: &Vec<wgpu::Buffer>,
+                backprop_data: &Vec<wgpu::Buffer>,
+                anchor: &pipelines::Device,
+                encoder: &mut wgpu::CommandEncoder,
+                batch_size: usize,) -> (wgpu::Buffer, Vec<Option<wgpu::Buffer>>) {
+        let device = &anchor.device;
+
+        let mut gpu_data = layer_data.iter();
+        let _layer_slope = gpu_data.next().unwrap();
+
+        let mut gpu_data = backprop_data.iter();
+        let layer_outputprime = gpu_data.next().unwrap();
+        let layer_input = gpu_data.next().unwrap();
+
+        //Create slope_grad pipeline
+        let slope_grad_uniforms = {
+            let uniform_data = [self.dimension as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let slope_grad_pipeline = pipelines::preluslopegrad::Pipeline::new::<f32>(anchor, (
+                &slope_grad_uniforms,
+                layer_input,
+                backprop_grad,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run slope_grad pipeline
+        slope_grad_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Create input_grad pipeline
+        let input_grad_pipeline = pipelines::elementmultiply::Pipeline::new::<f32>(anchor, (
+                &slope_grad_uniforms,
+                layer_outputprime,
+                backprop_grad,
+            ),
+            self.dimension,
+            batch_size,
+        );
+
+        //Run input_grad pipeline
+        input_grad_pipeline.run(encoder, self.dimension, batch_size);
+
+        //Return
+        let vec: Vec<Option<wgpu::Buffer>> = vec![Some(slope_grad_pipeline.output_buffer)];
+        (input_grad_pipeline.output_buffer, vec)
+    }
+}
@@ -0,0 +1,438 @@
+use crate::pipelines;
+
+use serde::{Serialize, Deserialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use futures::executor::block_on;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum RecurrentCell {
+    Rnn,
+    Lstm,
+    Gru,
+}
+
+impl RecurrentCell {
+    pub fn gate_count(&self) -> usize {
+        match self {
+            RecurrentCell::Rnn => 1,
+            RecurrentCell::Lstm => 4,
+            RecurrentCell::Gru => 3,
+        }
+    }
+
+    //Lstm carries its cell value alongside the hidden state
+    pub fn state_count(&self) -> usize {
+        match self {
+            RecurrentCell::Lstm => 2,
+            _ => 1,
+        }
+    }
+}
+
+//Each batch item is step_count steps of input_dimension features.
+//Weights are (gates * hidden) x (input + hidden + 1) with the bias as the last column.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Recurrent {
+    pub cell: RecurrentCell,
+    pub weights: Vec<f32>,
+    pub input_dimension: usize,
+    pub hidden_dimension: usize,
+    pub step_count: usize,
+    pub return_sequences: bool,
+    pub stateful: bool,
+    //Carried state of a stateful layer as of the last save, empty until then
+    #[serde(default)]
+    pub state: Vec<f32>,
+}
+
+struct ForwardOutput {
+    sequence: wgpu::Buffer,
+    gates: wgpu::Buffer,
+    recurrent: wgpu::Buffer,
+    cell: Option<wgpu::Buffer>,
+    state: wgpu::Buffer,
+}
+
+impl Recurrent {
+    fn gate_size(&self) -> usize {
+        self.cell.gate_count() * self.hidden_dimension
+    }
+
+    fn weight_size(&self) -> usize {
+        self.input_dimension + self.hidden_dimension + 1
+    }
+
+    fn uniforms(&self, anchor: &pipelines::Device, batch_size: usize) -> wgpu::Buffer {
+        let uniform_data = [
+            self.input_dimension as u32,
+            self.hidden_dimension as u32,
+            self.step_count as u32,
+            batch_size as u32,
+            self.return_sequences as u32,
+            self.cell.gate_count() as u32,
+        ];
+        anchor.device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: Some("Uniform Buffer"),
+                contents: bytemuck::bytes_of(&uniform_data),
+                usage: wgpu::BufferUsages::UNIFORM,
+            }
+        )
+    }
+
+    fn run_cell(&self,
+                input: &wgpu::Buffer,
+                layer_weights: &wgpu::Buffer,
+                layer_state: &wgpu::Buffer,
+                anchor: &pipelines::Device,
+                encoder: &mut wgpu::CommandEncoder,
+                batch_size: usize,) -> ForwardOutput {
+        //Create cell pipeline
+        let cell_uniforms = self.uniforms(anchor, batch_size);
+        let buffers = (&cell_uniforms, input, layer_weights, layer_state);
+        let (d, h, t) = (self.input_dimension, self.hidden_dimension, self.step_count);
+
+        //Run cell pipeline
+        match self.cell {
+            RecurrentCell::Rnn => {
+                let cell_pipeline = pipelines::rnnforward::Pipeline::new::<f32>(anchor, buffers, d, h, t, batch_size);
+                cell_pipeline.run(encoder, d, h, t, batch_size);
+                ForwardOutput {
+                    sequence: cell_pipeline.output_buffer,
+                    gates: cell_pipeline.gate_buffer,
+                    recurrent: cell_pipeline.recurrent_buffer,
+                    cell: None,
+                    state: cell_pipeline.state_buffer,
+                }
+            },
+            RecurrentCell::Lstm => {
+                let cell_pipeline = pipelines::lstmforward::Pipeline::new::<f32>(anchor, buffers, d, h, t, batch_size);
+                cell_pipeline.run(encoder, d, h, t, batch_size);
+                ForwardOutput {
+                    sequence: cell_pipeline.output_buffer,
+                    gates: cell_pipeline.gate_buffer,
+                    recurrent: cell_pipeline.recurrent_buffer,
+                    cell: Some(cell_pipeline.cell_buffer),
+                    state: cell_pipeline.state_buffer,
+                }
+            },
+            RecurrentCell::Gru => {
+                let cell_pipeline = pipelines::gruforward::Pipeline::new::<f32>(anchor, buffers, d, h, t, batch_size);
+                cell_pipeline.run(encoder, d, h, t, batch_size);
+                ForwardOutput {
+                    sequence: cell_pipeline.output_buffer,
+                    gates: cell_pipeline.gate_buffer,
+                    recurrent: cell_pipeline.recurrent_buffer,
+                    cell: None,
+                    state: cell_pipeline.state_buffer,
+                }
+            },
+        }
+    }
+
+    fn zero_state(&self, anchor: &pipelines::Device) -> wgpu::Buffer {
+        anchor.device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&vec![0f32; self.cell.state_count() * self.hidden_dimension][..]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        )
+    }
+
+    fn layer_output(&self,
+                    sequence: wgpu::Buffer,
+                    anchor: &pipelines::Device,
+                    encoder: &mut wgpu::CommandEncoder,
+                    batch_size: usize,) -> wgpu::Buffer {
+        if self.return_sequences {
+            return sequence;
+        }
+
+        //Create last step pipeline
+        let last_uniforms = {
+            let uniform_data = [self.hidden_dimension as u32, batch_size as u32, self.step_count as u32];
+            anchor.device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let last_pipeline = pipelines::sequencelast::Pipeline::new::<f32>(anchor, (
+                &last_uniforms,
+                &sequence,
+            ),
+            self.hidden_dimension,
+            batch_size,
+        );
+
+        //Run last step pipeline
+        last_pipeline.run(encoder, self.hidden_dimension, batch_size);
+
+        //Return
+        last_pipeline.output_buffer
+    }
+}
+
+#[typetag::serde]
+impl super::NetworkLayer for Recurrent {
+    fn trainable_parameters(&self) -> Vec<usize> {
+        //Carried state is updated in the forward pass
+        vec![0]
+    }
+
+    fn get_topology(&self) -> Vec<(usize, usize)> {
+        let vec: Vec<(usize, usize)> = vec![(self.gate_size(), self.weight_size()), (self.cell.state_count() * self.hidden_dimension, 1)];
+
+        //Return
+        vec
+    }
+
+    fn load_to_gpu(&self, anchor: &pipelines::Device,) -> Vec<wgpu::Buffer> {
+        let device = &anchor.device;
+        let mut vec: Vec<wgpu::Buffer> = Vec::with_capacity(2);
+
+        let layer_weights: wgpu::Buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&self.weights[..]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+        vec.push(layer_weights);
+
+        //Stateful layers carry on from the state they were saved with, everything else starts at zero
+        let layer_state: wgpu::Buffer = match self.stateful && self.state.len() == self.cell.state_count() * self.hidden_dimension {
+            true => device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::cast_slice(&self.state[..]),
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                }
+            ),
+            false => self.zero_state(anchor),
+        };
+        vec.push(layer_state);
+
+        vec
+    }
+
+    fn save_from_gpu(&mut self, anchor: &pipelines::Device, data: &Vec<wgpu::Buffer>) {
+        let queue = &anchor.queue;
+        let device = &anchor.device;
+        let type_size = std::mem::size_of::<f32>();
+        let weight_count = self.gate_size() * self.weight_size();
+
+        let mut gpu_data = data.iter();
+        let layer_weights = gpu_data.next().unwrap();
+        let layer_state = gpu_data.next().unwrap();
+
+        //Keep the carried state so checkpoints resume mid sequence
+        if self.stateful {
+            match crate::network::metrics::read_buffer(layer_state, anchor, self.cell.state_count() * self.hidden_dimension) {
+                Some(state) => self.state = state,
+                None => eprintln!("Failed to save layer_state to cpu"),
+            }
+        }
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        //Copy to readable buffer
+        let layer_weight_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Staging buffer"),
+                size: (type_size * weight_count) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }
+        );
+        encoder.copy_buffer_to_buffer(
+            layer_weights, 0,
+            &layer_weight_buffer, 0,
+            (type_size * weight_count) as wgpu::BufferAddress,
+        );
+
+        //Submit commands to gpu
+        queue.submit(Some(encoder.finish()));
+
+        //Create future of the computation
+        let layer_weight_slice = layer_weight_buffer.slice(..);
+        let layer_weight_future = layer_weight_slice.map_async(wgpu::MapMode::Read);
+
+        //Register mapping callbacks
+        device.poll(wgpu::Maintain::Wait);
+
+        //Read from gpu
+        block_on(async {
+            match layer_weight_future.await {
+                Ok(()) => {
+                    //Get buffer contents
+                    let data = layer_weight_slice.get_mapped_range();
+                    //Convert to f32
+                    let result: Vec<f32> = data.chunks_exact(type_size).map(|b| *bytemuck::from_bytes::<f32>(b)).collect();
+                     //Drop mapped view
+                    drop(data);
+                    //Unmap buffer
+                    layer_weight_buffer.unmap();
+
+                    //Save data
+                    self.weights = result;
+                }
+                Err(e) => {
+                    eprintln!("Failed to save layer_weights to cpu: {}", e);
+                }
+            }
+        });
+    }
+
+    fn forward(&self,
+               input: &wgpu::Buffer,
+               layer_data: &Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> wgpu::Buffer {
+        let mut gpu_data = layer_data.iter();
+        let layer_weights = gpu_data.next().unwrap();
+        let layer_state = gpu_data.next().unwrap();
+
+        //Stateful layers read their carried state, forward_stateful advances it
+        let cell_output = self.run_cell(input, layer_weights, layer_state, anchor, encoder, batch_size);
+
+        //Return
+        self.layer_output(cell_output.sequence, anchor, encoder, batch_size)
+    }
+
+    fn forward_for_backprop(&self,
+               input: &wgpu::Buffer,
+               layer_data: &mut Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> (wgpu::Buffer, Vec<wgpu::Buffer>) {
+        let mut gpu_data = layer_data.iter_mut();
+        let layer_weights = gpu_data.next().unwrap();
+        let layer_state = gpu_data.next().unwrap();
+
+        let cell_output = self.run_cell(input, layer_weights, layer_state, anchor, encoder, batch_size);
+
+        //Create vec for return
+        let mut vec: Vec<wgpu::Buffer> = vec![cell_output.gates, cell_output.recurrent];
+        if let Some(cell) = cell_output.cell {
+            vec.push(cell);
+        }
+
+        //Update mutable values
+        //Gradients are truncated at the batch boundary
+        if self.stateful {
+            *layer_state = cell_output.state;
+        }
+
+        //Return
+        (self.layer_output(cell_output.sequence, anchor, encoder, batch_size), vec)
+    }
+
+    fn forward_stateful(&self,
+               input: &wgpu::Buffer,
+               layer_data: &mut Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> wgpu::Buffer {
+        let mut gpu_data = layer_data.iter_mut();
+        let layer_weights = gpu_data.next().unwrap();
+        let layer_state = gpu_data.next().unwrap();
+
+        let cell_output = self.run_cell(input, layer_weights, layer_state, anchor, encoder, batch_size);
+
+        //Update mutable values
+        if self.stateful {
+            *layer_state = cell_output.state;
+        }
+
+        //Return
+        self.layer_output(cell_output.sequence, anchor, encoder, batch_size)
+    }
+
+    fn reset_state(&self, anchor: &pipelines::Device, layer_data: &mut Vec<wgpu::Buffer>) {
+        layer_data[1] = self.zero_state(anchor);
+    }
+
+    fn backprop(&self,
+                backprop_grad: &wgpu::Buffer,
+                layer_data: &Vec<wgpu::Buffer>,
+                backprop_data: &Vec<wgpu::Buffer>,
+                anchor: &pipelines::Device,
+                encoder: &mut wgpu::CommandEncoder,
+                batch_size: usize,) -> (wgpu::Buffer, Vec<Option<wgpu::Buffer>>) {
+        let mut gpu_data = layer_data.iter();
+        let layer_weights = gpu_data.next().unwrap();
+        let _layer_state = gpu_data.next().unwrap();
+
+        let mut gpu_data = backprop_data.iter();
+        let layer_gates = gpu_data.next().unwrap();
+        let layer_recurrent = gpu_data.next().unwrap();
+        let layer_cell = match self.cell {
+            RecurrentCell::Lstm => Some(gpu_data.next().unwrap()),
+            _ => None,
+        };
+        let layer_input = gpu_data.next().unwrap();
+
+        //Create backward pipeline
+        let backward_uniforms = self.uniforms(anchor, batch_size);
+        let (d, h, t) = (self.input_dimension, self.hidden_dimension, self.step_count);
+        let buffers = (&backward_uniforms, backprop_grad, layer_weights, layer_gates, layer_recurrent);
+
+        //Run backward pipeline
+        let (input_grad, gate_grad) = match (self.cell, layer_cell) {
+            (RecurrentCell::Lstm, Some(layer_cell)) => {
+                let (uniforms, grad, weights, gates, recurrent) = buffers;
+                let backward_pipeline = pipelines::lstmbackward::Pipeline::new::<f32>(anchor, (
+                        uniforms,
+                        grad,
+                        weights,
+                        gates,
+                        recurrent,
+                        layer_cell,
+                    ),
+                    d, h, t, batch_size,
+                );
+                backward_pipeline.run(encoder, d, h, t, batch_size);
+                (backward_pipeline.output_buffer, backward_pipeline.dgate_buffer)
+            },
+            (RecurrentCell::Gru, _) => {
+                let backward_pipeline = pipelines::grubackward::Pipeline::new::<f32>(anchor, buffers, d, h, t, batch_size);
+                backward_pipeline.run(encoder, d, h, t, batch_size);
+                (backward_pipeline.output_buffer, backward_pipeline.dgate_buffer)
+            },
+            _ => {
+                let backward_pipeline = pipelines::rnnbackward::Pipeline::new::<f32>(anchor, buffers, d, h, t, batch_size);
+                backward_pipeline.run(encoder, d, h, t, batch_size);
+                (backward_pipeline.output_buffer, backward_pipeline.dgate_buffer)
+            },
+        };
+
+        //Create weight_grad pipeline
+        let weight_grad_pipeline = pipelines::recurrentweightgrad::Pipeline::new::<f32>(anchor, (
+                &backward_uniforms,
+                layer_input,
+                layer_recurrent,
+                &gate_grad,
+            ),
+            self.gate_size(),
+            self.weight_size(),
+        );
+
+        //Run weight_grad pipeline
+        weight_grad_pipeline.run(encoder, self.gate_size(), self.weight_size());
+
+        //Return
+        let vec: Vec<Option<wgpu::Buffer>> = vec![Some(weight_grad_pipeline.output_buffer), None];
+        (input_grad, vec)
+    }
+}
@@ -0,0 +1,131 @@
+use crate::pipelines;
+
+use serde::{Serialize, Deserialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+//Rows of an item are already laid out with the last axis fastest so only the shape changes
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Reshape {
+    pub input_shape: Vec<usize>,
+    pub output_shape: Vec<usize>,
+}
+
+#[typetag::serde]
+impl super::NetworkLayer for Reshape {
+    fn get_topology(&self) -> Vec<(usize, usize)> {
+        #[allow(unused_mut)]
+        let mut vec: Vec<(usize, usize)> = Vec::with_capacity(0);
+
+        vec
+    }
+
+    fn load_to_gpu(&self, anchor: &pipelines::Device,) -> Vec<wgpu::Buffer> {
+        let _device = &anchor.device;
+        #[allow(unused_mut)]
+        let mut vec: Vec<wgpu::Buffer> = Vec::with_capacity(0);
+
+        vec
+    }
+
+    fn save_from_gpu(&mut self, _anchor: &pipelines::Device, _data: &Vec<wgpu::Buffer>) {
+        //Nothing to do
+    }
+
+    fn forward(&self,
+               input: &wgpu::Buffer,
+               layer_data: &Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> wgpu::Buffer {
+        let device = &anchor.device;
+        let dimension = self.input_shape.iter().product::<usize>();
+
+        let mut _gpu_data = layer_data.iter();
+
+        //Create copy pipeline
+        let copy_uniforms = {
+            let uniform_data = [dimension as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let copy_pipeline = pipelines::copymatrix::Pipeline::new::<f32>(anchor, (
+                &copy_uniforms,
+                input,
+            ),
+            dimension,
+            batch_size,
+        );
+
+        //Run copy pipeline
+        copy_pipeline.run(encoder, dimension, batch_size);
+
+        //Return
+        copy_pipeline.output_buffer
+    }
+
+    fn forward_for_backprop(&self,
+               input: &wgpu::Buffer,
+               layer_data: &mut Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> (wgpu::Buffer, Vec<wgpu::Buffer>) {
+        let output = self.forward(input, layer_data, anchor, encoder, batch_size);
+
+        //Create vec for return
+        #[allow(unused_mut)]
+        let mut vec: Vec<wgpu::Buffer> = Vec::with_capacity(1);
+
+        //Return
+        (output, vec)
+    }
+
+    fn backprop(&self,
+                backprop_grad: &wgpu::Buffer,
+                layer_data: &Vec<wgpu::Buffer>,
+                backprop_data: &Vec<wgpu::Buffer>,
+                anchor: &pipelines::Device,
+                encoder: &mut wgpu::CommandEncoder,
+                batch_size: usize,) -> (wgpu::Buffer, Vec<Option<wgpu::Buffer>>) {
+        let device = &anchor.device;
+        let dimension = self.input_shape.iter().product::<usize>();
+
+        let mut _gpu_data = layer_data.iter();
+
+        let mut gpu_data = backprop_data.iter();
+        let _layer_input = gpu_data.next().unwrap();
+
+        //Create input_grad pipeline
+        let input_grad_uniforms = {
+            let uniform_data = [dimension as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let input_grad_pipeline = pipelines::copymatrix::Pipeline::new::<f32>(anchor, (
+                &input_grad_uniforms,
+                backprop_grad,
+            ),
+            dimension,
+            batch_size,
+        );
+
+        //Run input_grad pipeline
+        input_grad_pipeline.run(encoder, dimension, batch_size);
+
+        //Return
+        #[allow(unused_mut)]
+        let mut vec: Vec<Option<wgpu::Buffer>> = Vec::with_capacity(0);
+        (input_grad_pipeline.output_buffer, vec)
+    }
+}
@@ -10,6 +10,10 @@ pub struct Softmax {
 
 #[typetag::serde]
 impl super::NetworkLayer for Softmax {
+    fn outputs_probabilities(&self) -> bool {
+        true
+    }
+
     fn get_topology(&self) -> Vec<(usize, usize)> {
         #[allow(unused_mut)]
         let mut vec: Vec<(usize, usize)> = Vec::with_capacity(0);
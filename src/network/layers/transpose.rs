@@ -0,0 +1,158 @@
+use crate::pipelines;
+
+use serde::{Serialize, Deserialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+pub const MAX_RANK: usize = 4;
+
+//Output axis i is input axis permutation[i]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Transpose {
+    pub input_shape: Vec<usize>,
+    pub permutation: Vec<usize>,
+}
+
+impl Transpose {
+    fn uniform_data(shape: &[usize], permutation: &[usize], batch_size: usize) -> [u32; 11] {
+        let mut uniform_data = [0u32; 11];
+        uniform_data[0] = shape.iter().product::<usize>() as u32;
+        uniform_data[1] = batch_size as u32;
+        uniform_data[2] = shape.len() as u32;
+        for (axis, (&dim, &source)) in shape.iter().zip(permutation.iter()).enumerate() {
+            uniform_data[3 + axis] = dim as u32;
+            uniform_data[3 + MAX_RANK + axis] = source as u32;
+        }
+        uniform_data
+    }
+
+    //Undoing the permutation starts from the permuted shape
+    fn inverse(&self) -> (Vec<usize>, Vec<usize>) {
+        let output_shape: Vec<usize> = self.permutation.iter().map(|&axis| self.input_shape[axis]).collect();
+        let mut inverse = vec![0; self.permutation.len()];
+        for (axis, &source) in self.permutation.iter().enumerate() {
+            inverse[source] = axis;
+        }
+        (output_shape, inverse)
+    }
+}
+
+#[typetag::serde]
+impl super::NetworkLayer for Transpose {
+    fn get_topology(&self) -> Vec<(usize, usize)> {
+        #[allow(unused_mut)]
+        let mut vec: Vec<(usize, usize)> = Vec::with_capacity(0);
+
+        vec
+    }
+
+    fn load_to_gpu(&self, anchor: &pipelines::Device,) -> Vec<wgpu::Buffer> {
+        let _device = &anchor.device;
+        #[allow(unused_mut)]
+        let mut vec: Vec<wgpu::Buffer> = Vec::with_capacity(0);
+
+        vec
+    }
+
+    fn save_from_gpu(&mut self, _anchor: &pipelines::Device, _data: &Vec<wgpu::Buffer>) {
+        //Nothing to do
+    }
+
+    fn forward(&self,
+               input: &wgpu::Buffer,
+               layer_data: &Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> wgpu::Buffer {
+        let device = &anchor.device;
+        let dimension = self.input_shape.iter().product::<usize>();
+
+        let mut _gpu_data = layer_data.iter();
+
+        //Create permute pipeline
+        let permute_uniforms = {
+            let uniform_data = Transpose::uniform_data(&self.input_shape, &self.permutation, batch_size);
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let permute_pipeline = pipelines::permuteaxes::Pipeline::new::<f32>(anchor, (
+                &permute_uniforms,
+                input,
+            ),
+            dimension,
+            batch_size,
+        );
+
+        //Run permute pipeline
+        permute_pipeline.run(encoder, dimension, batch_size);
+
+        //Return
+        permute_pipeline.output_buffer
+    }
+
+    fn forward_for_backprop(&self,
+               input: &wgpu::Buffer,
+               layer_data: &mut Vec<wgpu::Buffer>,
+               anchor: &pipelines::Device,
+               encoder: &mut wgpu::CommandEncoder,
+               batch_size: usize,) -> (wgpu::Buffer, Vec<wgpu::Buffer>) {
+        let output = self.forward(input, layer_data, anchor, encoder, batch_size);
+
+        //Create vec for return
+        #[allow(unused_mut)]
+        let mut vec: Vec<wgpu::Buffer> = Vec::with_capacity(1);
+
+        //Return
+        (output, vec)
+    }
+
+    fn backprop(&self,
+                backprop_grad: &wgpu::Buffer,
+                layer_data: &Vec<wgpu::Buffer>,
+                backprop_data: &Vec<wgpu::Buffer>,
+                anchor: &pipelines::Device,
+                encoder: &mut wgpu::CommandEncoder,
+                batch_size: usize,) -> (wgpu::Buffer, Vec<Option<wgpu::Buffer>>) {
+        let device = &anchor.device;
+        let dimension = self.input_shape.iter().product::<usize>();
+
+        let mut _gpu_data = layer_data.iter();
+
+        let mut gpu_data = backprop_data.iter();
+        let _layer_input = gpu_data.next().unwrap();
+
+        //Create input_grad pipeline
+        let (output_shape, inverse) = self.inverse();
+        let input_grad_uniforms = {
+            let uniform_data = Transpose::uniform_data(&output_shape, &inverse, batch_size);
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        let input_grad_pipeline = pipelines::permuteaxes::Pipeline::new::<f32>(anchor, (
+                &input_grad_uniforms,
+                backprop_grad,
+            ),
+            dimension,
+            batch_size,
+        );
+
+        //Run input_grad pipeline
+        input_grad_pipeline.run(encoder, dimension, batch_size);
+
+        //Return
+        #[allow(unused_mut)]
+        let mut vec: Vec<Option<wgpu::Buffer>> = Vec::with_capacity(0);
+        (input_grad_pipeline.output_buffer, vec)
+    }
+}
@@ -0,0 +1,455 @@
+use crate::pipelines;
+use crate::data::DataSet;
+
+use futures::executor::block_on;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::perceptron::Network;
+
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    pub item_count: usize,
+    //confusion_matrix[true_class][predicted_class]
+    pub confusion_matrix: Vec<Vec<usize>>,
+    pub top_k: usize,
+    pub top_k_hits: usize,
+    //Only computed for binary tasks
+    pub roc_auc: Option<f32>,
+}
+
+#[allow(dead_code)]
+impl Evaluation {
+    pub fn class_count(&self) -> usize {
+        self.confusion_matrix.len()
+    }
+
+    pub fn accuracy(&self) -> f32 {
+        let correct: usize = (0..self.class_count()).map(|class| self.confusion_matrix[class][class]).sum();
+        ratio(correct, self.item_count)
+    }
+
+    pub fn top_k_accuracy(&self) -> f32 {
+        ratio(self.top_k_hits, self.item_count)
+    }
+
+    //Classes that are never predicted or never occur score 0
+    pub fn precision(&self, class: usize) -> f32 {
+        ratio(self.confusion_matrix[class][class], self.predicted_count(class))
+    }
+
+    pub fn recall(&self, class: usize) -> f32 {
+        ratio(self.confusion_matrix[class][class], self.true_count(class))
+    }
+
+    pub fn f1(&self, class: usize) -> f32 {
+        harmonic_mean(self.precision(class), self.recall(class))
+    }
+
+    //Unweighted mean over classes
+    pub fn macro_precision(&self) -> f32 {
+        (0..self.class_count()).map(|class| self.precision(class)).sum::<f32>() / self.class_count() as f32
+    }
+
+    pub fn macro_recall(&self) -> f32 {
+        (0..self.class_count()).map(|class| self.recall(class)).sum::<f32>() / self.class_count() as f32
+    }
+
+    pub fn macro_f1(&self) -> f32 {
+        (0..self.class_count()).map(|class| self.f1(class)).sum::<f32>() / self.class_count() as f32
+    }
+
+    //Pooled over classes, equal to accuracy when every item has exactly one label
+    pub fn micro_precision(&self) -> f32 {
+        let true_positives: usize = (0..self.class_count()).map(|class| self.confusion_matrix[class][class]).sum();
+        let predicted: usize = (0..self.class_count()).map(|class| self.predicted_count(class)).sum();
+        ratio(true_positives, predicted)
+    }
+
+    pub fn micro_recall(&self) -> f32 {
+        let true_positives: usize = (0..self.class_count()).map(|class| self.confusion_matrix[class][class]).sum();
+        let actual: usize = (0..self.class_count()).map(|class| self.true_count(class)).sum();
+        ratio(true_positives, actual)
+    }
+
+    pub fn micro_f1(&self) -> f32 {
+        harmonic_mean(self.micro_precision(), self.micro_recall())
+    }
+
+    fn predicted_count(&self, class: usize) -> usize {
+        self.confusion_matrix.iter().map(|row| row[class]).sum()
+    }
+
+    fn true_count(&self, class: usize) -> usize {
+        self.confusion_matrix[class].iter().sum()
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f32 {
+    match denominator {
+        0 => 0.0,
+        _ => numerator as f32 / denominator as f32,
+    }
+}
+
+fn harmonic_mean(a: f32, b: f32) -> f32 {
+    match a + b > 0.0 {
+        true => 2.0 * a * b / (a + b),
+        false => 0.0,
+    }
+}
+
+//Probability a random positive scores above a random negative, ties count half
+//None when either class is missing
+pub fn roc_auc(scores: &[f32], labels: &[u32]) -> Option<f32> {
+    let mut ranked: Vec<(f32, u32)> = scores.iter().cloned().zip(labels.iter().cloned()).collect();
+    ranked.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let positive_count = ranked.iter().filter(|&&(_, label)| label != 0).count();
+    let negative_count = ranked.len() - positive_count;
+    if positive_count == 0 || negative_count == 0 {
+        return None;
+    }
+
+    //Sum of positive ranks with tied scores sharing their mean rank
+    let mut positive_rank_sum = 0.0f64;
+    let mut start = 0;
+    while start < ranked.len() {
+        let mut end = start;
+        while end < ranked.len() && ranked[end].0 == ranked[start].0 {
+            end += 1;
+        }
+        let mean_rank = (start + end + 1) as f64 / 2.0;
+        let tied_positives = ranked[start..end].iter().filter(|&&(_, label)| label != 0).count();
+        positive_rank_sum += mean_rank * tied_positives as f64;
+        start = end;
+    }
+
+    let u = positive_rank_sum - (positive_count * (positive_count + 1)) as f64 / 2.0;
+    Some((u / (positive_count * negative_count) as f64) as f32)
+}
+
+//Streams the whole data set through the network in order, labels are class indices
+//A single output is treated as a binary probability, ROC-AUC is computed for one or two outputs
+pub fn evaluate<D: Clone + bytemuck::Pod>(network: &Network,
+                                          network_data: &Vec<Vec<wgpu::Buffer>>,
+                                          data_set: &DataSet<D, u32>,
+                                          anchor: &pipelines::Device,
+                                          batch_size: usize,
+                                          top_k: usize,) -> Evaluation {
+    let queue = &anchor.queue;
+    let device = &anchor.device;
+    let output_size = network.get_output_size();
+    let class_count = output_size.max(2);
+    let binary = output_size <= 2;
+
+    //Load data to gpu
+    let mut count_buffer = device.create_buffer_init(
+        &BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&vec![0f32; class_count * class_count][..]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        }
+    );
+
+    let mut hit_buffer = device.create_buffer_init(
+        &BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[0f32]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        }
+    );
+
+    let k_buffer = device.create_buffer_init(
+        &BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[top_k as u32]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        }
+    );
+
+    //A single output from a logits cost is split at 0 rather than 0.5
+    let threshold_buffer = device.create_buffer_init(
+        &BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[network.decision_threshold()]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        }
+    );
+
+    let mut scores: Vec<f32> = Vec::new();
+    let mut score_labels: Vec<u32> = Vec::new();
+    for batch in data_set.batches(batch_size).into_iter() {
+        let current_batch_size = batch.get_size();
+        let batch_labels = batch.get_labels();
+        //The confusion matrix kernel indexes its counts by label
+        if let Some(label) = batch_labels.iter().find(|&&label| label as usize >= class_count) {
+            panic!("Label {} is out of range for {} classes", label, class_count);
+        }
+        let prediction = network.feedforward::<D>(&batch.get_data(), network_data, anchor, current_batch_size);
+
+        let label_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&batch_labels[..]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        //Create argmax pipeline
+        let prediction_uniforms = pipelines::uniform_buffer(anchor, &[output_size as u32, current_batch_size as u32]);
+        let argmax_pipeline = pipelines::argmax::Pipeline::new::<f32>(anchor, (
+                &prediction_uniforms,
+                &prediction,
+                &threshold_buffer,
+            ),
+            output_size,
+            current_batch_size,
+        );
+
+        //Run argmax pipeline
+        argmax_pipeline.run(&mut encoder, output_size, current_batch_size);
+
+        //Create confusion pipeline
+        let confusion_uniforms = pipelines::uniform_buffer(anchor, &[class_count as u32, current_batch_size as u32]);
+        let confusion_pipeline = pipelines::confusionmatrix::Pipeline::new::<f32>(anchor, (
+                &confusion_uniforms,
+                &argmax_pipeline.output_buffer,
+                &label_buffer,
+                &count_buffer,
+            ),
+            class_count,
+            current_batch_size,
+        );
+
+        //Run confusion pipeline
+        confusion_pipeline.run(&mut encoder, class_count, current_batch_size);
+
+        //Create top k pipeline, a single output has no ranking
+        let top_k_pipeline = match output_size > 1 {
+            true => {
+                let top_k_pipeline = pipelines::topkhits::Pipeline::new::<f32>(anchor, (
+                        &prediction_uniforms,
+                        &prediction,
+                        &label_buffer,
+                        &k_buffer,
+                        &hit_buffer,
+                    ),
+                    output_size,
+                    current_batch_size,
+                );
+
+                //Run top k pipeline
+                top_k_pipeline.run(&mut encoder, output_size, current_batch_size);
+                Some(top_k_pipeline)
+            },
+            false => None,
+        };
+
+        //Submit encoder
+        queue.submit(Some(encoder.finish()));
+
+        count_buffer = confusion_pipeline.output_buffer;
+        if let Some(top_k_pipeline) = top_k_pipeline {
+            hit_buffer = top_k_pipeline.output_buffer;
+        }
+
+        //Scores of the positive class are needed on the cpu to rank them
+        if binary {
+            let batch_prediction = read_buffer(&prediction, anchor, output_size * current_batch_size).unwrap();
+            scores.extend(batch_prediction.chunks(output_size).map(|item| item[output_size - 1]));
+            score_labels.extend(batch_labels);
+        }
+    }
+
+    //Read counts from gpu
+    let counts = read_buffer(&count_buffer, anchor, class_count * class_count).unwrap();
+    let confusion_matrix: Vec<Vec<usize>> = (0..class_count).map(|true_class| {
+        (0..class_count).map(|predicted_class| counts[true_class * class_count + predicted_class] as usize).collect()
+    }).collect();
+    let top_k_hits = match output_size > 1 {
+        true => read_buffer(&hit_buffer, anchor, 1).unwrap()[0] as usize,
+        false => confusion_matrix[0][0] + confusion_matrix[1][1],
+    };
+
+    Evaluation {
+        item_count: data_set.get_size(),
+        confusion_matrix,
+        top_k,
+        top_k_hits,
+        roc_auc: match binary {
+            true => roc_auc(&scores, &score_labels),
+            false => None,
+        },
+    }
+}
+
+pub(crate) fn read_buffer(buffer: &wgpu::Buffer, anchor: &pipelines::Device, size: usize) -> Option<Vec<f32>> {
+    let queue = &anchor.queue;
+    let device = &anchor.device;
+    let type_size = std::mem::size_of::<f32>();
+
+    //Create command buffer encoder
+    let mut encoder = device.create_command_encoder(
+        &wgpu::CommandEncoderDescriptor {
+            label: None,
+        }
+    );
+
+    //Copy to readable buffer
+    let staging_buffer = device.create_buffer(
+        &wgpu::BufferDescriptor {
+            label: Some("Staging buffer"),
+            size: (type_size * size) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }
+    );
+    encoder.copy_buffer_to_buffer(
+        buffer, 0,
+        &staging_buffer, 0,
+        (type_size * size) as wgpu::BufferAddress,
+    );
+
+    //Submit commands to gpu
+    queue.submit(Some(encoder.finish()));
+
+    //Create future of the computation
+    let buffer_slice = staging_buffer.slice(..);
+    let buffer_future = buffer_slice.map_async(wgpu::MapMode::Read);
+
+    //Register mapping callbacks
+    device.poll(wgpu::Maintain::Wait);
+
+    //Read from gpu
+    block_on(async {
+        match buffer_future.await {
+            Ok(()) => {
+                //Get buffer contents
+                let data = buffer_slice.get_mapped_range();
+                //Convert to f32
+                let result: Vec<f32> = data.chunks_exact(type_size).map(|b| *bytemuck::from_bytes::<f32>(b)).collect();
+                //Drop mapped view
+                drop(data);
+                //Unmap buffer
+                staging_buffer.unmap();
+
+                //Return
+                Some(result)
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                None
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.00001
+    }
+
+    //Rows are true classes and columns predicted classes
+    fn evaluation(confusion_matrix: Vec<Vec<usize>>) -> Evaluation {
+        Evaluation {
+            item_count: confusion_matrix.iter().flatten().sum(),
+            confusion_matrix,
+            top_k: 1,
+            top_k_hits: 0,
+            roc_auc: None,
+        }
+    }
+
+    #[test]
+    fn per_class_metrics() {
+        let evaluation = evaluation(vec![
+            vec![5, 1, 0],
+            vec![2, 3, 1],
+            vec![0, 0, 4],
+        ]);
+        assert!(close(evaluation.accuracy(), 12.0 / 16.0));
+        assert!(close(evaluation.precision(0), 5.0 / 7.0));
+        assert!(close(evaluation.recall(0), 5.0 / 6.0));
+        assert!(close(evaluation.f1(0), 2.0 * (5.0 / 7.0) * (5.0 / 6.0) / (5.0 / 7.0 + 5.0 / 6.0)));
+        assert!(close(evaluation.precision(1), 3.0 / 4.0));
+        assert!(close(evaluation.recall(1), 3.0 / 6.0));
+        assert!(close(evaluation.precision(2), 4.0 / 5.0));
+        assert!(close(evaluation.recall(2), 1.0));
+    }
+
+    #[test]
+    fn macro_and_micro_averages() {
+        let evaluation = evaluation(vec![
+            vec![5, 1, 0],
+            vec![2, 3, 1],
+            vec![0, 0, 4],
+        ]);
+        let precisions = [5.0 / 7.0, 3.0 / 4.0, 4.0 / 5.0];
+        let recalls = [5.0 / 6.0, 3.0 / 6.0, 1.0];
+        let f1s: Vec<f32> = precisions.iter().zip(recalls.iter()).map(|(p, r)| 2.0 * p * r / (p + r)).collect();
+        assert!(close(evaluation.macro_precision(), precisions.iter().sum::<f32>() / 3.0));
+        assert!(close(evaluation.macro_recall(), recalls.iter().sum::<f32>() / 3.0));
+        assert!(close(evaluation.macro_f1(), f1s.iter().sum::<f32>() / 3.0));
+        //Every item has one label so the micro averages equal accuracy
+        assert!(close(evaluation.micro_precision(), 0.75));
+        assert!(close(evaluation.micro_recall(), 0.75));
+        assert!(close(evaluation.micro_f1(), 0.75));
+    }
+
+    #[test]
+    fn missing_classes_score_zero() {
+        //Class 1 is never predicted and class 2 never occurs
+        let evaluation = evaluation(vec![
+            vec![3, 0, 1],
+            vec![2, 0, 0],
+            vec![0, 0, 0],
+        ]);
+        assert!(close(evaluation.precision(1), 0.0));
+        assert!(close(evaluation.recall(1), 0.0));
+        assert!(close(evaluation.f1(1), 0.0));
+        assert!(close(evaluation.recall(2), 0.0));
+        assert!(close(evaluation.precision(2), 0.0));
+        assert!(close(evaluation.macro_recall(), (3.0 / 4.0) / 3.0));
+    }
+
+    #[test]
+    fn roc_auc_ranks_positives_above_negatives() {
+        assert_eq!(roc_auc(&[0.1, 0.2, 0.8, 0.9], &[0, 0, 1, 1]), Some(1.0));
+        assert_eq!(roc_auc(&[0.9, 0.8, 0.2, 0.1], &[0, 0, 1, 1]), Some(0.0));
+        //Three of the four positive-negative pairs are ordered correctly
+        assert!(close(roc_auc(&[0.1, 0.4, 0.35, 0.8], &[0, 0, 1, 1]).unwrap(), 0.75));
+    }
+
+    #[test]
+    fn roc_auc_counts_ties_as_half() {
+        assert!(close(roc_auc(&[0.5, 0.5, 0.5, 0.5], &[0, 1, 0, 1]).unwrap(), 0.5));
+        //Pairs (0.5, 0.5) tie, the other three are ordered correctly
+        assert!(close(roc_auc(&[0.5, 0.5, 0.2, 0.9], &[1, 0, 0, 1]).unwrap(), 3.5 / 4.0));
+    }
+
+    #[test]
+    fn roc_auc_needs_both_classes() {
+        assert_eq!(roc_auc(&[0.3, 0.7], &[1, 1]), None);
+        assert_eq!(roc_auc(&[0.3, 0.7], &[0, 0]), None);
+        assert_eq!(roc_auc(&[], &[]), None);
+    }
+
+    #[test]
+    fn single_output_threshold_follows_the_cost() {
+        use crate::network::{CostFunction, LayerType};
+        let logits = Network::new(2, vec![LayerType::FullyConnected(1)], CostFunction::BinaryCrossEntropyWithLogits(None));
+        let probabilities = Network::new(2, vec![LayerType::FullyConnected(1)], CostFunction::BinaryCrossEntropy(None));
+        assert_eq!(logits.decision_threshold(), 0.0);
+        assert_eq!(probabilities.decision_threshold(), 0.5);
+    }
+}
@@ -1,17 +1,161 @@
-mod layers;
-mod cost;
+pub mod layers;
+pub mod cost;
 pub mod perceptron;
+pub mod graph;
+pub mod multihead;
+pub mod metrics;
+pub mod format;
+pub mod json;
+
+use std::fmt;
+use serde::{Serialize, Deserialize};
+
+pub use layers::{NetworkLayer, LayerGenerator};
 
 #[allow(dead_code)]
+#[derive(Debug)]
 pub enum LayerType {
     FullyConnected(usize),
+    Embedding(usize, usize),
+    PaddedEmbedding(usize, usize, u32),
     Batchnorm,
+    LayerNorm,
     Relu,
+    Prelu,
     Softmax,
+    Rnn(Recurrence),
+    Lstm(Recurrence),
+    Gru(Recurrence),
+    MultiHeadAttention(Attention),
+    Flatten,
+    Reshape(Vec<usize>),
+    //Output axis i is input axis permutation[i]
+    Transpose(Vec<usize>),
+    //GLSL expressions of x for the function and its derivative
+    Elementwise(String, String),
+    Custom(Box<dyn LayerGenerator>),
+}
+
+//Shape of a single batch item, the last axis is contiguous
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shape {
+    pub dims: Vec<usize>,
+}
+
+impl Shape {
+    pub fn size(&self) -> usize {
+        self.dims.iter().product()
+    }
+
+    pub fn rank(&self) -> usize {
+        self.dims.len()
+    }
+
+    pub fn with_trailing(&self, dim: usize) -> Self {
+        let mut dims = self.dims.clone();
+        dims.push(dim);
+        Shape {
+            dims,
+        }
+    }
+}
+
+impl From<usize> for Shape {
+    fn from(size: usize) -> Self {
+        Shape {
+            dims: vec![size],
+        }
+    }
+}
+
+impl From<Vec<usize>> for Shape {
+    fn from(dims: Vec<usize>) -> Self {
+        Shape {
+            dims,
+        }
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.dims)
+    }
+}
+
+//Input to a recurrent layer is step_count steps per batch item, shaped [step_count, features] or flat
+#[derive(Debug)]
+pub struct Recurrence {
+    pub hidden_size: usize,
+    pub step_count: usize,
+    pub return_sequences: bool,
+    //Carry state across batches, advanced by training and feedforward_stateful and saved with the network
+    pub stateful: bool,
+}
+
+//Input to an attention layer is sequence_length tokens per batch item, split evenly between heads
+#[derive(Debug)]
+pub struct Attention {
+    pub head_count: usize,
+    pub sequence_length: usize,
+    pub causal: bool,
+}
+
+//Penalty of l1 * |w| + l2 * w^2 summed over a layer's trainable parameters
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Regularisation {
+    pub l1: f32,
+    pub l2: f32,
 }
 
 #[allow(dead_code)]
+#[derive(Debug)]
 pub enum CostFunction {
     SquaredError,
-    CrossEntropy,
+    //Optional per-class weights and a label smoothing factor
+    CrossEntropy(Option<Vec<f32>>, f32),
+    //As CrossEntropy, but targets are u32 class indices rather than one-hot vectors
+    SparseCrossEntropy(Option<Vec<f32>>, f32),
+    //Optional per-class weights for positive targets
+    BinaryCrossEntropy(Option<Vec<f32>>),
+    BinaryCrossEntropyWithLogits(Option<Vec<f32>>),
+    Huber(f32),
+    MeanAbsoluteError,
+    LogCosh,
+    Quantile(f32),
+    //Temperature, targets are soft probabilities and the prediction is treated as logits
+    KlDivergence(f32),
+    //Gamma focusing and alpha scaling factors, on one-hot targets
+    Focal(f32, f32),
+    //Margins for embedding costs, targets are u32 class indices and items are compared within the batch
+    Contrastive(f32),
+    TripletMargin(f32),
+}
+
+//Output head on top of a shared trunk, weight scales both its cost and the gradient it sends to the trunk
+pub struct OutputHead {
+    pub name: String,
+    pub layers: Vec<LayerType>,
+    pub cost: CostFunction,
+    pub weight: f32,
+}
+
+//Student loss is (1 - alpha) * hard label cost + alpha * kl divergence to the teacher at temperature
+//The teacher is frozen, teacher_data are its weights on the gpu
+#[derive(Clone, Copy)]
+pub struct Distillation<'a> {
+    pub teacher: &'a perceptron::Network,
+    pub teacher_data: &'a Vec<Vec<wgpu::Buffer>>,
+    pub temperature: f32,
+    pub alpha: f32,
+}
+
+//Writes to a temporary file next to filelocation and renames it over the original,
+//so an interrupted save never leaves a truncated file behind
+pub fn write_atomic(bytes: &[u8], filelocation: &str) {
+    use std::io::Write;
+    let temporary_location = format!("{}.tmp", filelocation);
+    let mut file = std::fs::File::create(&temporary_location).unwrap();
+    file.write_all(bytes).unwrap();
+    file.sync_all().unwrap();
+    std::fs::rename(&temporary_location, filelocation).unwrap();
 }
@@ -0,0 +1,487 @@
+use crate::pipelines;
+
+use serde::{Serialize, Deserialize};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use super::layers;
+use super::cost;
+
+#[derive(Serialize, Deserialize)]
+struct Head {
+    name: String,
+    layers: Vec<Box<dyn layers::NetworkLayer>>,
+    cost_function: Box<dyn cost::CostFunction>,
+    output_size: usize,
+    weight: f32,
+}
+
+//Network data and grads are indexed by trunk layers followed by the layers of each head in order
+#[derive(Serialize, Deserialize)]
+pub struct MultiHeadNetwork {
+    trunk: Vec<Box<dyn layers::NetworkLayer>>,
+    trunk_size: usize,
+    heads: Vec<Head>,
+}
+
+#[allow(dead_code)]
+impl MultiHeadNetwork {
+    pub fn new<S: Into<super::Shape>>(input_shape: S, trunk_types: Vec<super::LayerType>, output_heads: Vec<super::OutputHead>) -> Self {
+        assert!(!output_heads.is_empty(), "Expected at least one output head");
+        let (trunk_shape, trunk, _) = generate_layers(input_shape.into(), trunk_types, "Trunk");
+
+        let mut heads: Vec<Head> = Vec::with_capacity(output_heads.len());
+        for output_head in output_heads.into_iter() {
+            assert!(heads.iter().all(|head| head.name != output_head.name), "Head {} is defined twice", output_head.name);
+            let (head_shape, head_layers, ends_in_softmax) = generate_layers(trunk_shape.clone(), output_head.layers, &format!("Head {}", output_head.name));
+            let output_size = head_shape.size();
+            let cost_function = match ends_in_softmax {
+                true => cost::generate_softmax_cost(output_size, output_head.cost),
+                false => cost::generate_cost(output_size, output_head.cost),
+            };
+            heads.push(Head {
+                name: output_head.name,
+                layers: head_layers,
+                cost_function,
+                output_size,
+                weight: output_head.weight,
+            });
+        }
+
+        MultiHeadNetwork {
+            trunk,
+            trunk_size: trunk_shape.size(),
+            heads,
+        }
+    }
+
+    //Labels and outputs are ordered the same way
+    pub fn head_names(&self) -> Vec<&str> {
+        self.heads.iter().map(|head| head.name.as_str()).collect()
+    }
+
+    pub fn get_topology(&self) -> Vec<Vec<(usize, usize)>> {
+        let mut vec: Vec<Vec<(usize, usize)>> = Vec::new();
+        for layer in self.trunk.iter().chain(self.heads.iter().flat_map(|head| head.layers.iter())) {
+            vec.push(layer.get_topology());
+        }
+
+        //Return
+        vec
+    }
+
+    pub fn save_to_file(&self, filelocation: &str) {
+        super::format::save_to_file(self, filelocation);
+    }
+
+    pub fn load_from_file(filelocation: &str) -> Self {
+        super::format::load_from_file(filelocation)
+    }
+
+    pub fn load_to_gpu(&self, anchor: &pipelines::Device,) -> Vec<Vec<wgpu::Buffer>> {
+        let mut vec: Vec<Vec<wgpu::Buffer>> = Vec::new();
+        for layer in self.trunk.iter().chain(self.heads.iter().flat_map(|head| head.layers.iter())) {
+            vec.push(layer.load_to_gpu(anchor));
+        }
+        vec
+    }
+
+    pub fn save_from_gpu(&mut self, anchor: &pipelines::Device, data: &Vec<Vec<wgpu::Buffer>>) {
+        let layer_iterator = self.trunk.iter_mut().chain(self.heads.iter_mut().flat_map(|head| head.layers.iter_mut()));
+        for (layer, layer_data) in layer_iterator.zip(data) {
+            layer.save_from_gpu(anchor, layer_data);
+        }
+    }
+
+    //Returns the output of each head with its name
+    pub fn feedforward<T: bytemuck::Pod>(&self,
+                                         input: &[T],
+                                         network_data: &[Vec<wgpu::Buffer>],
+                                         anchor: &pipelines::Device,
+                                         batch_size: usize,) -> Vec<(String, wgpu::Buffer)> {
+        let queue = &anchor.queue;
+        let device = &anchor.device;
+
+        //Load input to gpu
+        self.check_input(input);
+        let input_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(input),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        //Feed input through the trunk then each head
+        let trunk_output = forward_layers(&self.trunk, input_buffer, &network_data[..self.trunk.len()], anchor, &mut encoder, batch_size);
+        let mut outputs: Vec<(String, wgpu::Buffer)> = Vec::with_capacity(self.heads.len());
+        for (head, head_range) in self.heads.iter().zip(self.head_ranges()) {
+            let head_input = pipelines::copy_buffer(&trunk_output, self.trunk_size * batch_size, anchor, &mut encoder);
+            let head_output = forward_layers(&head.layers, head_input, &network_data[head_range], anchor, &mut encoder, batch_size);
+            outputs.push((head.name.clone(), head_output));
+        }
+
+        //Submit encoder
+        queue.submit(Some(encoder.finish()));
+
+        //Return
+        outputs
+    }
+
+    //Labels are the raw bytes of each head's targets, e.g. bytemuck::cast_slice(&class_indices)
+    //Returns the weighted total and the mean cost of each head with its name
+    pub fn cost<I: bytemuck::Pod>(&self,
+                                  input: &[I],
+                                  labels: &Vec<&[u8]>,
+                                  network_data: &[Vec<wgpu::Buffer>],
+                                  anchor: &pipelines::Device,
+                                  batch_size: usize,) -> (wgpu::Buffer, Vec<(String, wgpu::Buffer)>) {
+        assert_eq!(labels.len(), self.heads.len(), "Expected labels for each head");
+        let queue = &anchor.queue;
+        let device = &anchor.device;
+
+        //Load data to gpu
+        self.check_input(input);
+        let input_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(input),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        //Feed input through the trunk
+        let trunk_output = forward_layers(&self.trunk, input_buffer, &network_data[..self.trunk.len()], anchor, &mut encoder, batch_size);
+
+        let scalar_uniforms = pipelines::uniform_buffer(anchor, &[1, 1]);
+        let mut total: Option<wgpu::Buffer> = None;
+        let mut head_costs: Vec<(String, wgpu::Buffer)> = Vec::with_capacity(self.heads.len());
+        for ((head, head_range), head_labels) in self.heads.iter().zip(self.head_ranges()).zip(labels.iter()) {
+            let label_buffer = device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: None,
+                    contents: head_labels,
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                }
+            );
+
+            //Feed trunk output through layers not fused into the cost function
+            let unfused_layers = head.layers.len() - head.cost_function.fused_layers();
+            let head_input = pipelines::copy_buffer(&trunk_output, self.trunk_size * batch_size, anchor, &mut encoder);
+            let head_data = &network_data[head_range][..unfused_layers];
+            let prediction = forward_layers(&head.layers[..unfused_layers], head_input, head_data, anchor, &mut encoder, batch_size);
+
+            //Compute cost
+            let item_costs = head.cost_function.cost(
+                &prediction,
+                &label_buffer,
+                anchor,
+                &mut encoder,
+                batch_size,
+            );
+
+            //Create mean pipeline
+            let mean_uniforms = pipelines::uniform_buffer(anchor, &[1, batch_size as u32]);
+            let mean_pipeline = pipelines::batchmean::Pipeline::new::<f32>(anchor, (
+                    &mean_uniforms,
+                    &item_costs,
+                ),
+                1,
+                batch_size,
+            );
+
+            //Run mean pipeline
+            mean_pipeline.run(&mut encoder, 1, batch_size);
+
+            //Create weight pipeline
+            let weight_buffer = device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::cast_slice(&[head.weight]),
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                }
+            );
+            let weight_pipeline = pipelines::scalarmultiply::Pipeline::new::<f32>(anchor, (
+                    &scalar_uniforms,
+                    &weight_buffer,
+                    &mean_pipeline.output_buffer,
+                ),
+                1,
+                1,
+            );
+
+            //Run weight pipeline
+            weight_pipeline.run(&mut encoder, 1, 1);
+
+            //Accumulate weighted cost
+            total = Some(match total.take() {
+                Some(previous_total) => {
+                    let accumulate_pipeline = pipelines::elementadd::Pipeline::new::<f32>(anchor, (
+                            &scalar_uniforms,
+                            &previous_total,
+                            &weight_pipeline.output_buffer,
+                        ),
+                        1,
+                        1,
+                    );
+                    accumulate_pipeline.run(&mut encoder, 1, 1);
+                    accumulate_pipeline.output_buffer
+                },
+                None => weight_pipeline.output_buffer,
+            });
+            head_costs.push((head.name.clone(), mean_pipeline.output_buffer));
+        }
+
+        //Submit encoder
+        queue.submit(Some(encoder.finish()));
+
+        //Return
+        (total.unwrap(), head_costs)
+    }
+
+    //Labels are the raw bytes of each head's targets, head gradients are weighted and summed into the trunk
+    pub fn backprop<I: bytemuck::Pod>(&self,
+                                      input: &[I],
+                                      labels: &Vec<&[u8]>,
+                                      network_data: &mut [Vec<wgpu::Buffer>],
+                                      anchor: &pipelines::Device,
+                                      batch_size: usize,) -> Vec<Vec<Option<wgpu::Buffer>>> {
+        assert_eq!(labels.len(), self.heads.len(), "Expected labels for each head");
+        let queue = &anchor.queue;
+        let device = &anchor.device;
+
+        //Load data to gpu
+        self.check_input(input);
+        let input_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(input),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        //Feed input through the trunk to get info for backprop
+        let trunk_length = self.trunk.len();
+        let (trunk_output, trunk_intermediate) = forward_layers_for_backprop(&self.trunk, input_buffer, &mut network_data[..trunk_length], anchor, &mut encoder, batch_size);
+
+        //Feed trunk output through each head and back to the trunk
+        let trunk_uniforms = pipelines::uniform_buffer(anchor, &[self.trunk_size as u32, batch_size as u32]);
+        let mut trunk_grad: Option<wgpu::Buffer> = None;
+        let mut head_grads: Vec<Vec<Option<wgpu::Buffer>>> = Vec::new();
+        for ((head, head_range), head_labels) in self.heads.iter().zip(self.head_ranges()).zip(labels.iter()) {
+            let label_buffer = device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: None,
+                    contents: head_labels,
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                }
+            );
+
+            let unfused_layers = head.layers.len() - head.cost_function.fused_layers();
+            let head_input = pipelines::copy_buffer(&trunk_output, self.trunk_size * batch_size, anchor, &mut encoder);
+            let head_data = &mut network_data[head_range][..unfused_layers];
+            let (prediction, head_intermediate) = forward_layers_for_backprop(&head.layers[..unfused_layers], head_input, head_data, anchor, &mut encoder, batch_size);
+
+            let cost_grad = head.cost_function.cost_prime(
+                &prediction,
+                &label_buffer,
+                anchor,
+                &mut encoder,
+                batch_size,
+            );
+
+            //Create weight pipeline
+            let weight_buffer = device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::cast_slice(&[head.weight]),
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                }
+            );
+            let weight_uniforms = pipelines::uniform_buffer(anchor, &[head.output_size as u32, batch_size as u32]);
+            let weight_pipeline = pipelines::scalarmultiply::Pipeline::new::<f32>(anchor, (
+                    &weight_uniforms,
+                    &weight_buffer,
+                    &cost_grad,
+                ),
+                head.output_size,
+                batch_size,
+            );
+
+            //Run weight pipeline
+            weight_pipeline.run(&mut encoder, head.output_size, batch_size);
+
+            //Perform backprop through the head
+            let (head_input_grad, mut layer_grads) = backprop_layers(&head.layers[..unfused_layers], weight_pipeline.output_buffer, head_data, &head_intermediate, anchor, &mut encoder, batch_size);
+
+            //Fused layers are handled by the cost function and have no grads
+            layer_grads.extend((unfused_layers..head.layers.len()).map(|_| Vec::new()));
+            head_grads.extend(layer_grads);
+
+            //Accumulate trunk grad
+            trunk_grad = Some(match trunk_grad.take() {
+                Some(previous_grad) => {
+                    let accumulate_pipeline = pipelines::elementadd::Pipeline::new::<f32>(anchor, (
+                            &trunk_uniforms,
+                            &previous_grad,
+                            &head_input_grad,
+                        ),
+                        self.trunk_size,
+                        batch_size,
+                    );
+                    accumulate_pipeline.run(&mut encoder, self.trunk_size, batch_size);
+                    accumulate_pipeline.output_buffer
+                },
+                None => head_input_grad,
+            });
+        }
+
+        //Perform backprop through the trunk
+        let (_, mut backprop_values) = backprop_layers(&self.trunk, trunk_grad.unwrap(), &network_data[..trunk_length], &trunk_intermediate, anchor, &mut encoder, batch_size);
+        backprop_values.extend(head_grads);
+
+        //Submit encoder
+        queue.submit(Some(encoder.finish()));
+
+        //Return
+        backprop_values
+    }
+
+    //Without a trunk the first layer of each head sees the raw input
+    fn check_input<I: bytemuck::Pod>(&self, input: &[I]) {
+        match self.trunk.first() {
+            Some(layer) => layer.check_input(bytemuck::cast_slice(input)),
+            None => for head in &self.heads {
+                if let Some(layer) = head.layers.first() {
+                    layer.check_input(bytemuck::cast_slice(input));
+                }
+            },
+        }
+    }
+
+    //Indices into network data of the layers of each head
+    fn head_ranges(&self) -> Vec<std::ops::Range<usize>> {
+        let mut start = self.trunk.len();
+        self.heads.iter().map(|head| {
+            let range = start..start + head.layers.len();
+            start = range.end;
+            range
+        }).collect()
+    }
+}
+
+//Returns the output shape and whether the last layer is a softmax
+fn generate_layers(input_shape: super::Shape, layer_types: Vec<super::LayerType>, context: &str) -> (super::Shape, Vec<Box<dyn layers::NetworkLayer>>, bool) {
+    let mut layers: Vec<Box<dyn layers::NetworkLayer>> = Vec::new();
+    let mut current_shape = input_shape;
+    let ends_in_softmax = matches!(layer_types.last(), Some(super::LayerType::Softmax));
+
+    for (index, layer_type) in layer_types.into_iter().enumerate() {
+        let layer_name = format!("{:?}", layer_type);
+        let (output_shape, layer) = match layers::generate_layer(&current_shape, layer_type) {
+            Ok(generated) => generated,
+            Err(e) => panic!("{} layer {} ({}) can't take input of shape {}: {}", context, index, layer_name, current_shape, e),
+        };
+        layers.push(layer);
+        current_shape = output_shape;
+    }
+
+    //Return
+    (current_shape, layers, ends_in_softmax)
+}
+
+fn forward_layers(layers: &[Box<dyn layers::NetworkLayer>],
+                  input: wgpu::Buffer,
+                  network_data: &[Vec<wgpu::Buffer>],
+                  anchor: &pipelines::Device,
+                  encoder: &mut wgpu::CommandEncoder,
+                  batch_size: usize,) -> wgpu::Buffer {
+    let layer_iterator = layers.iter().zip(network_data.iter());
+    layer_iterator.fold(input, |buffer, (layer, layer_data)| {
+        layer.forward(
+            &buffer,
+            layer_data,
+            anchor,
+            encoder,
+            batch_size,
+        )
+    })
+}
+
+//The input of each layer is appended to its intermediate values
+fn forward_layers_for_backprop(layers: &[Box<dyn layers::NetworkLayer>],
+                               input: wgpu::Buffer,
+                               network_data: &mut [Vec<wgpu::Buffer>],
+                               anchor: &pipelines::Device,
+                               encoder: &mut wgpu::CommandEncoder,
+                               batch_size: usize,) -> (wgpu::Buffer, Vec<Vec<wgpu::Buffer>>) {
+    let mut current_output = input;
+    let mut intermediate_values: Vec<Vec<wgpu::Buffer>> = Vec::new();
+    for (layer, layer_data) in layers.iter().zip(network_data.iter_mut()) {
+        let (new_output, mut data) = layer.forward_for_backprop(
+            &current_output,
+            layer_data,
+            anchor,
+            encoder,
+            batch_size,
+        );
+        data.push(current_output);
+        intermediate_values.push(data);
+        current_output = new_output;
+    }
+
+    //Return
+    (current_output, intermediate_values)
+}
+
+//Returns the grad of the first layer's input and the grads of each layer
+fn backprop_layers(layers: &[Box<dyn layers::NetworkLayer>],
+                   grad: wgpu::Buffer,
+                   network_data: &[Vec<wgpu::Buffer>],
+                   intermediate_values: &[Vec<wgpu::Buffer>],
+                   anchor: &pipelines::Device,
+                   encoder: &mut wgpu::CommandEncoder,
+                   batch_size: usize,) -> (wgpu::Buffer, Vec<Vec<Option<wgpu::Buffer>>>) {
+    let backprop_iter = layers.iter()
+        .zip(network_data.iter())
+        .zip(intermediate_values.iter())
+        .rev();
+    let mut backprop_grad = grad;
+    let mut vec: Vec<Vec<Option<wgpu::Buffer>>> = Vec::new();
+    for ((layer, layer_data), intermediate_data) in backprop_iter {
+        let (layer_input_grad, layer_grads) = layer.backprop(
+            &backprop_grad,
+            layer_data,
+            intermediate_data,
+            anchor,
+            encoder,
+            batch_size,
+        );
+        vec.push(layer_grads);
+        backprop_grad = layer_input_grad;
+    }
+    vec.reverse();
+
+    //Return
+    (backprop_grad, vec)
+}
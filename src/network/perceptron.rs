@@ -1,7 +1,6 @@
 use crate::pipelines;
 
 use serde::{Serialize, Deserialize};
-use std::fs::File;
 
 use super::layers;
 use super::cost;
@@ -11,26 +10,63 @@ pub struct Network {
     layers: Vec<Box<dyn layers::NetworkLayer>>,
     cost_function: Box<dyn cost::CostFunction>,
     output_size: usize,
+    //One entry per layer, empty for networks saved before regularisation existed
+    #[serde(default)]
+    regularisation: Vec<Option<super::Regularisation>>,
 }
 
 #[allow(dead_code)]
 impl Network {
-    pub fn new(input_size: usize, layer_types: Vec<super::LayerType>, cost: super::CostFunction) -> Self {
+    pub fn new<S: Into<super::Shape>>(input_shape: S, layer_types: Vec<super::LayerType>, cost: super::CostFunction) -> Self {
         let mut layers: Vec<Box<dyn layers::NetworkLayer>> = Vec::new();
-        let mut current_output: usize = input_size;
+        let mut current_shape: super::Shape = input_shape.into();
+        let ends_in_softmax = matches!(layer_types.last(), Some(super::LayerType::Softmax));
 
-        for layer_type in layer_types.into_iter() {
-            let (output_size, layer) = layers::generate_layer(current_output, layer_type);
+        for (index, layer_type) in layer_types.into_iter().enumerate() {
+            let layer_name = format!("{:?}", layer_type);
+            let (output_shape, layer) = match layers::generate_layer(&current_shape, layer_type) {
+                Ok(generated) => generated,
+                Err(e) => panic!("Layer {} ({}) can't take input of shape {}: {}", index, layer_name, current_shape, e),
+            };
             layers.push(layer);
-            current_output = output_size;
+            current_shape = output_shape;
         }
-        
-        let cost_function = cost::generate_cost(current_output, cost);
+        let current_output = current_shape.size();
+
+        let cost_function = match ends_in_softmax {
+            true => cost::generate_softmax_cost(current_output, cost),
+            false => cost::generate_cost(current_output, cost),
+        };
+
+        let regularisation = layers.iter().map(|_| None).collect();
 
         Network {
             layers,
             cost_function,
             output_size: current_output,
+            regularisation,
+        }
+    }
+
+    pub fn set_regularisation(&mut self, layer_index: usize, regularisation: Option<super::Regularisation>) {
+        assert!(layer_index < self.layers.len(), "Layer {} out of range for {} layers", layer_index, self.layers.len());
+        self.regularisation.resize(self.layers.len(), None);
+        self.regularisation[layer_index] = regularisation;
+    }
+
+    fn layer_regularisation(&self, layer_index: usize) -> Option<&super::Regularisation> {
+        self.regularisation.get(layer_index).and_then(|regularisation| regularisation.as_ref())
+    }
+
+    pub fn get_output_size(&self) -> usize {
+        self.output_size
+    }
+
+    //Where a single output splits the two classes, the sigmoid of 0 is 0.5
+    pub fn decision_threshold(&self) -> f32 {
+        match self.cost_function.expects_logits() {
+            true => 0.0,
+            false => 0.5,
         }
     }
 
@@ -46,14 +82,20 @@ impl Network {
     }
 
     pub fn save_to_file(&self, filelocation: &str) {
-        let file = File::create(filelocation).unwrap();
-        bincode::serialize_into(&file, &self).unwrap();
+        super::format::save_to_file(self, filelocation);
     }
 
     pub fn load_from_file(filelocation: &str) -> Self {
-        let file = File::open(filelocation).unwrap();
-        let network: Network = bincode::deserialize_from(&file).unwrap();
-        network
+        super::format::load_from_file(filelocation)
+    }
+
+    //Readable JSON copy of the topology, cost and weights, see network::json
+    pub fn export_json(&self, filelocation: &str, storage: super::json::WeightStorage) {
+        super::json::export(self, filelocation, storage);
+    }
+
+    pub fn import_json(filelocation: &str) -> Self {
+        super::json::import(filelocation)
     }
     
     pub fn load_to_gpu(&self, anchor: &pipelines::Device,) -> Vec<Vec<wgpu::Buffer>> {
@@ -81,6 +123,7 @@ impl Network {
         
         //Load input to gpu
         use wgpu::util::{BufferInitDescriptor, DeviceExt};
+        self.check_input(input);
         let input_buffer = device.create_buffer_init(
             &BufferInitDescriptor {
                 label: None,
@@ -115,9 +158,63 @@ impl Network {
         output_buffer
     }
 
-    pub fn cost<T: bytemuck::Pod>(&self,
-                                  prediction: &wgpu::Buffer,
+    //Like feedforward but stateful layers carry their state on to the next call
+    pub fn feedforward_stateful<T: bytemuck::Pod>(&self,
+                                                  input: &[T],
+                                                  network_data: &mut [Vec<wgpu::Buffer>],
+                                                  anchor: &pipelines::Device,
+                                                  batch_size: usize,) -> wgpu::Buffer {
+        let queue = &anchor.queue;
+        let device = &anchor.device;
+
+        //Load input to gpu
+        use wgpu::util::{BufferInitDescriptor, DeviceExt};
+        self.check_input(input);
+        let input_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(input),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        //Feed input through layers to get output
+        let layer_iterator = self.layers.iter().zip(network_data.iter_mut());
+        let output_buffer = layer_iterator.fold(input_buffer, |buffer, (layer, layer_data)| {
+            layer.forward_stateful(
+                &buffer,
+                layer_data,
+                anchor,
+                &mut encoder,
+                batch_size,
+            )
+        });
+
+        //Submit encoder
+        queue.submit(Some(encoder.finish()));
+
+        //Return
+        output_buffer
+    }
+
+    //Starts every stateful layer over from a zero state, e.g. between independent sequences
+    pub fn reset_state(&self, anchor: &pipelines::Device, network_data: &mut [Vec<wgpu::Buffer>]) {
+        for (layer, layer_data) in self.layers.iter().zip(network_data.iter_mut()) {
+            layer.reset_state(anchor, layer_data);
+        }
+    }
+
+    pub fn cost<I: bytemuck::Pod, T: bytemuck::Pod>(&self,
+                                  input: &[I],
                                   labels: &Vec<T>,
+                                  network_data: &Vec<Vec<wgpu::Buffer>>,
                                   anchor: &pipelines::Device,
                                   batch_size: usize,
                                   take_mean: bool) -> wgpu::Buffer {
@@ -126,6 +223,15 @@ impl Network {
 
         //Load data to gpu
         use wgpu::util::{BufferInitDescriptor, DeviceExt};
+        self.check_input(input);
+        let input_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(input),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
         let label_buffer = device.create_buffer_init(
             &BufferInitDescriptor {
                 label: None,
@@ -140,6 +246,19 @@ impl Network {
             }
         );
 
+        //Feed input through layers not fused into the cost function
+        let unfused_layers = self.layers.len() - self.cost_function.fused_layers();
+        let layer_iterator = self.layers[..unfused_layers].iter().zip(network_data);
+        let prediction = layer_iterator.fold(input_buffer, |buffer, (layer, layer_data)| {
+            layer.forward(
+                &buffer,
+                layer_data,
+                anchor,
+                &mut encoder,
+                batch_size,
+            )
+        });
+
         //Compute cost
         let item_costs = self.cost_function.cost(
             &prediction,
@@ -148,6 +267,36 @@ impl Network {
             &mut encoder,
             batch_size,
         );
+
+        //Add regularisation penalty to the cost of each item
+        let item_costs = match self.regularisation_penalty(network_data, anchor, &mut encoder) {
+            Some(penalty) => {
+                //Create penalty pipeline
+                let penalty_uniforms = {
+                    let uniform_data = [1_u32, batch_size as u32];
+                    device.create_buffer_init(
+                        &BufferInitDescriptor {
+                            label: Some("Uniform Buffer"),
+                            contents: bytemuck::bytes_of(&uniform_data),
+                            usage: wgpu::BufferUsages::UNIFORM,
+                        }
+                    )
+                };
+                let penalty_pipeline = pipelines::addvectortobatch::Pipeline::new::<f32>(anchor, (
+                        &penalty_uniforms,
+                        &item_costs,
+                        &penalty,
+                    ),
+                    1,
+                    batch_size,
+                );
+
+                //Run penalty pipeline
+                penalty_pipeline.run(&mut encoder, 1, batch_size);
+                penalty_pipeline.output_buffer
+            },
+            None => item_costs,
+        };
         match take_mean {
             true =>{
                 //Create mean pipeline
@@ -188,8 +337,8 @@ impl Network {
         }
     }
     
-    pub fn backprop<T: bytemuck::Pod>(&self,
-                                      input: &Vec<T>,
+    pub fn backprop<I: bytemuck::Pod, T: bytemuck::Pod>(&self,
+                                      input: &[I],
                                       labels: &Vec<T>,
                                       network_data: &mut Vec<Vec<wgpu::Buffer>>,
                                       anchor: &pipelines::Device,
@@ -199,6 +348,7 @@ impl Network {
 
         //Load data to gpu
         use wgpu::util::{BufferInitDescriptor, DeviceExt};
+        self.check_input(input);
         let input_buffer = device.create_buffer_init(
             &BufferInitDescriptor {
                 label: None,
@@ -222,8 +372,205 @@ impl Network {
             }
         );
 
+        //Perform backprop against the labels
+        let backprop_values = self.backprop_buffers(input_buffer, network_data, anchor, &mut encoder, batch_size, |prediction, encoder| {
+            self.cost_function.cost_prime(
+                prediction,
+                &label_buffer,
+                anchor,
+                encoder,
+                batch_size,
+            )
+        });
+        
+        //Submit encoder
+        queue.submit(Some(encoder.finish()));
+
+        //Return
+        backprop_values
+    }
+
+    //Trains against both the labels and the soft targets of a frozen teacher with the same output size
+    //Both networks' outputs before their fused layers are treated as logits, so a trailing Softmax must be fused into the cost
+    pub fn distill<I: bytemuck::Pod, T: bytemuck::Pod>(&self,
+                                     input: &[I],
+                                     labels: &[T],
+                                     network_data: &mut [Vec<wgpu::Buffer>],
+                                     anchor: &pipelines::Device,
+                                     batch_size: usize,
+                                     distillation: &super::Distillation,) -> Vec<Vec<Option<wgpu::Buffer>>> {
+        use cost::CostFunction;
+        let teacher = distillation.teacher;
+        assert_eq!(teacher.output_size, self.output_size, "Teacher and student output sizes differ");
+        assert!(self.outputs_logits(), "Student ends in a softmax that its cost does not fuse, distillation needs logits");
+        assert!(teacher.outputs_logits(), "Teacher ends in a softmax that its cost does not fuse, distillation needs logits");
+        assert!(distillation.temperature > 0.0, "Temperature must be positive");
+        assert!(distillation.alpha >= 0.0 && distillation.alpha <= 1.0, "Alpha must be between 0 and 1");
+        let queue = &anchor.queue;
+        let device = &anchor.device;
+
+        //Load data to gpu
+        use wgpu::util::{BufferInitDescriptor, DeviceExt};
+        self.check_input(input);
+        let input_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(input),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+        
+        let label_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(labels),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        let temperature_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&[distillation.temperature]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        let alpha_buffer = device.create_buffer_init(
+            &BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&[distillation.alpha]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            }
+        );
+
+        //Create command buffer encoder
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: None,
+            }
+        );
+
+        let output_uniforms = {
+            let uniform_data = [self.output_size as u32, batch_size as u32];
+            device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: Some("Uniform Buffer"),
+                    contents: bytemuck::bytes_of(&uniform_data),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                }
+            )
+        };
+
+        //Create soft target pipeline from the teacher's logits
+        let teacher_logits = teacher.forward_unfused(&input_buffer, distillation.teacher_data, anchor, &mut encoder, batch_size);
+        let soft_target_pipeline = pipelines::temperaturesoftmax::Pipeline::new::<f32>(anchor, (
+                &output_uniforms,
+                &teacher_logits,
+                &temperature_buffer,
+            ),
+            self.output_size,
+            batch_size,
+        );
+
+        //Run soft target pipeline
+        soft_target_pipeline.run(&mut encoder, self.output_size, batch_size);
+
+        //Perform backprop against the mix of hard and soft losses
+        let soft_cost = cost::kldivergence::KlDivergence {
+            dimension: self.output_size,
+            temperature: distillation.temperature,
+            fused_layers: self.cost_function.fused_layers(),
+        };
+        let backprop_values = self.backprop_buffers(input_buffer, network_data, anchor, &mut encoder, batch_size, |prediction, encoder| {
+            let hard_grad = self.cost_function.cost_prime(
+                prediction,
+                &label_buffer,
+                anchor,
+                encoder,
+                batch_size,
+            );
+            let soft_grad = soft_cost.cost_prime(
+                prediction,
+                &soft_target_pipeline.output_buffer,
+                anchor,
+                encoder,
+                batch_size,
+            );
+
+            //Create mix pipeline
+            let mix_pipeline = pipelines::lerp::Pipeline::new::<f32>(anchor, (
+                    &output_uniforms,
+                    &hard_grad,
+                    &soft_grad,
+                    &alpha_buffer,
+                ),
+                self.output_size,
+                batch_size,
+            );
+
+            //Run mix pipeline
+            mix_pipeline.run(encoder, self.output_size, batch_size);
+            mix_pipeline.output_buffer
+        });
+        
+        //Submit encoder
+        queue.submit(Some(encoder.finish()));
+
+        //Return
+        backprop_values
+    }
+
+    //Only the first layer sees the raw input
+    fn check_input<I: bytemuck::Pod>(&self, input: &[I]) {
+        if let Some(layer) = self.layers.first() {
+            layer.check_input(bytemuck::cast_slice(input));
+        }
+    }
+
+    //Whether the output of the layers outside the cost function is logits rather than probabilities
+    fn outputs_logits(&self) -> bool {
+        let unfused_layers = self.layers.len() - self.cost_function.fused_layers();
+        !self.layers[..unfused_layers].last().is_some_and(|layer| layer.outputs_probabilities())
+    }
+
+    //Feeds input through the layers not fused into the cost function
+    fn forward_unfused(&self,
+                       input: &wgpu::Buffer,
+                       network_data: &[Vec<wgpu::Buffer>],
+                       anchor: &pipelines::Device,
+                       encoder: &mut wgpu::CommandEncoder,
+                       batch_size: usize,) -> wgpu::Buffer {
+        let unfused_layers = self.layers.len() - self.cost_function.fused_layers();
+        let layer_iterator = self.layers[..unfused_layers].iter().zip(network_data.iter());
+        let mut output: Option<wgpu::Buffer> = None;
+        for (layer, layer_data) in layer_iterator {
+            let layer_output = layer.forward(
+                output.as_ref().unwrap_or(input),
+                layer_data,
+                anchor,
+                encoder,
+                batch_size,
+            );
+            output = Some(layer_output);
+        }
+
+        //Return
+        output.expect("Network has no layers outside its cost function")
+    }
+
+    //Runs the unfused layers forward and back, cost_prime maps their output to the grad fed back through them
+    fn backprop_buffers<F>(&self,
+                           input_buffer: wgpu::Buffer,
+                           network_data: &mut [Vec<wgpu::Buffer>],
+                           anchor: &pipelines::Device,
+                           encoder: &mut wgpu::CommandEncoder,
+                           batch_size: usize,
+                           cost_prime: F) -> Vec<Vec<Option<wgpu::Buffer>>>
+    where F: FnOnce(&wgpu::Buffer, &mut wgpu::CommandEncoder) -> wgpu::Buffer {
         //Feed input through layers to get info for backprop
-        let layer_iterator = self.layers.iter().zip(network_data.iter_mut());
+        let unfused_layers = self.layers.len() - self.cost_function.fused_layers();
+        let layer_iterator = self.layers[..unfused_layers].iter().zip(network_data.iter_mut());
         let mut current_output = input_buffer;
         let intermediate_values = {
             let mut vec: Vec<Vec<wgpu::Buffer>> = Vec::new();
@@ -232,7 +579,7 @@ impl Network {
                     &current_output,
                     layer_data,
                     &anchor,
-                    &mut encoder,
+                    encoder,
                     batch_size,
                 );
                 data.push(current_output);
@@ -243,27 +590,22 @@ impl Network {
         };
         
         //Perform backprop
-        let backprop_iter = self.layers.iter()
+        let backprop_iter = self.layers[..unfused_layers].iter()
             .zip(network_data.iter())
             .zip(intermediate_values.iter())
             .rev();
-        let mut backprop_grad = self.cost_function.cost_prime(
-            &current_output,
-            &label_buffer,
-            &anchor,
-            &mut encoder,
-            batch_size,
-        );
+        let mut backprop_grad = cost_prime(&current_output, encoder);
 
         let backprop_values = {
-            let mut vec: Vec<Vec<Option<wgpu::Buffer>>> = Vec::new();
+            //Fused layers are handled by the cost function and have no grads
+            let mut vec: Vec<Vec<Option<wgpu::Buffer>>> = (unfused_layers..self.layers.len()).map(|_| Vec::new()).collect();
             for ((layer, layer_data), intermediate_data) in backprop_iter {
                 let (layer_input_grad, layer_grads) = layer.backprop(
                     &backprop_grad,
                     layer_data,
                     intermediate_data,
                     &anchor,
-                    &mut encoder,
+                    encoder,
                     batch_size,
                 );
                 vec.push(layer_grads);
@@ -272,11 +614,145 @@ impl Network {
             vec.reverse();
             vec
         };
-        
-        //Submit encoder
-        queue.submit(Some(encoder.finish()));
 
         //Return
-        backprop_values
+        self.regularisation_grads(backprop_values, network_data, anchor, encoder)
+    }
+
+    //Sum of the penalties of all regularised layers, None if no layer is regularised
+    fn regularisation_penalty(&self,
+                              network_data: &[Vec<wgpu::Buffer>],
+                              anchor: &pipelines::Device,
+                              encoder: &mut wgpu::CommandEncoder,) -> Option<wgpu::Buffer> {
+        let device = &anchor.device;
+        use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+        let mut total: Option<wgpu::Buffer> = None;
+        let layer_iterator = self.layers.iter()
+            .enumerate()
+            .zip(network_data.iter());
+        for ((layer_index, layer), layer_data) in layer_iterator {
+            let regularisation = match self.layer_regularisation(layer_index) {
+                Some(regularisation) => regularisation,
+                None => continue,
+            };
+
+            //Load data to gpu
+            let strength_buffer = device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::cast_slice(&[regularisation.l1, regularisation.l2]),
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                }
+            );
+
+            let topology = layer.get_topology();
+            for index in layer.trainable_parameters() {
+                let (output_size, input_size) = topology[index];
+                let previous_total = match total.take() {
+                    Some(buffer) => buffer,
+                    None => device.create_buffer_init(
+                        &BufferInitDescriptor {
+                            label: None,
+                            contents: bytemuck::cast_slice(&[0f32]),
+                            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                        }
+                    ),
+                };
+
+                //Create penalty pipeline
+                let penalty_uniforms = {
+                    let uniform_data = [output_size as u32, input_size as u32];
+                    device.create_buffer_init(
+                        &BufferInitDescriptor {
+                            label: Some("Uniform Buffer"),
+                            contents: bytemuck::bytes_of(&uniform_data),
+                            usage: wgpu::BufferUsages::UNIFORM,
+                        }
+                    )
+                };
+                let penalty_pipeline = pipelines::regularisationpenalty::Pipeline::new::<f32>(anchor, (
+                        &penalty_uniforms,
+                        &layer_data[index],
+                        &previous_total,
+                        &strength_buffer,
+                    ),
+                    output_size,
+                    input_size,
+                );
+
+                //Run penalty pipeline
+                penalty_pipeline.run(encoder, output_size, input_size);
+                total = Some(penalty_pipeline.output_buffer);
+            }
+        }
+
+        //Return
+        total
+    }
+
+    //Adds the gradient of each layer's penalty to the grads of its trainable parameters
+    fn regularisation_grads(&self,
+                            network_grads: Vec<Vec<Option<wgpu::Buffer>>>,
+                            network_data: &[Vec<wgpu::Buffer>],
+                            anchor: &pipelines::Device,
+                            encoder: &mut wgpu::CommandEncoder,) -> Vec<Vec<Option<wgpu::Buffer>>> {
+        let device = &anchor.device;
+        use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+        let layer_iterator = network_grads.into_iter()
+            .zip(self.layers.iter())
+            .enumerate()
+            .zip(network_data.iter());
+        layer_iterator.map(|((layer_index, (mut layer_grads, layer)), layer_data)| {
+            let regularisation = match self.layer_regularisation(layer_index) {
+                Some(regularisation) => regularisation,
+                None => return layer_grads,
+            };
+
+            //Load data to gpu
+            let strength_buffer = device.create_buffer_init(
+                &BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::cast_slice(&[regularisation.l1, regularisation.l2]),
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                }
+            );
+
+            let topology = layer.get_topology();
+            for index in layer.trainable_parameters() {
+                let grad = match layer_grads.get_mut(index).and_then(|grad| grad.take()) {
+                    Some(grad) => grad,
+                    None => continue,
+                };
+                let (output_size, input_size) = topology[index];
+
+                //Create grad pipeline
+                let grad_uniforms = {
+                    let uniform_data = [output_size as u32, input_size as u32];
+                    device.create_buffer_init(
+                        &BufferInitDescriptor {
+                            label: Some("Uniform Buffer"),
+                            contents: bytemuck::bytes_of(&uniform_data),
+                            usage: wgpu::BufferUsages::UNIFORM,
+                        }
+                    )
+                };
+                let grad_pipeline = pipelines::regularisationgrad::Pipeline::new::<f32>(anchor, (
+                        &grad_uniforms,
+                        &layer_data[index],
+                        &grad,
+                        &strength_buffer,
+                    ),
+                    output_size,
+                    input_size,
+                );
+
+                //Run grad pipeline
+                grad_pipeline.run(encoder, output_size, input_size);
+                layer_grads[index] = Some(grad_pipeline.output_buffer);
+            }
+            layer_grads
+        }).collect()
     }
 }
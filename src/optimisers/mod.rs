@@ -1,7 +1,9 @@
 use crate::pipelines;
 
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use serde::{Serialize, Deserialize};
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Stochasticgradientdescent {
     learning_rate: f32,
 }
@@ -13,6 +15,14 @@ impl Stochasticgradientdescent {
         }
     }
 
+    pub fn get_learning_rate(&self) -> f32 {
+        self.learning_rate
+    }
+
+    pub fn set_learning_rate(&mut self, learning_rate: f32) {
+        self.learning_rate = learning_rate;
+    }
+
     pub fn step(&mut self,
                 network_data: &mut Vec<Vec<wgpu::Buffer>>,
                 network_grad: &Vec<Vec<Option<wgpu::Buffer>>>,
@@ -0,0 +1,162 @@
+pub struct Pipeline {
+    pub output_buffer: wgpu::Buffer,
+    bind_group_0: wgpu::BindGroup,
+    compute_pipeline: wgpu::ComputePipeline,
+}
+
+impl Pipeline {
+    //Take an m x n matrix and an m x n matrix and compute absolute error along n
+    pub fn new<T: bytemuck::Pod>(anchor: &super::Device,
+                                 buffers: (&wgpu::Buffer, // uniform buffer
+                                           &wgpu::Buffer, // m x n matrix
+                                           &wgpu::Buffer),// m x n matrix
+                                 _m_size: usize,
+                                 n_size: usize,) -> Self {
+        let type_size = std::mem::size_of::<T>();
+        let device = &anchor.device;
+        
+        //Create/load buffers
+        
+        let uniform_buffer = buffers.0;
+        //0-0
+        
+        let prediction_buffer = buffers.1;
+        //0-1
+        
+        let ground_buffer = buffers.2;
+        //0-2
+        
+        let output_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Output buffer"),
+                size: (type_size * n_size) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }
+        );
+        //0-3
+        
+        //Create bind group(s)
+        let bind_group_layout_0 = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: Some("Absolute Error bind group layout 0"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(0),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage {
+                            read_only: false,
+                        },
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(0),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage {
+                            read_only: false,
+                        },
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(0),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage {
+                            read_only: false,
+                        },
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(0),
+                    },
+                    count: None,
+                },],
+            }
+        );
+        let bind_group_0 = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label:  Some("Absolute Error bind group 0"),
+                layout: &bind_group_layout_0,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: prediction_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: ground_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: output_buffer.as_entire_binding(),
+                },],
+            }
+        );
+
+        //Create compute pipeline
+        let cs_src = include_str!("shader.comp");
+        let mut compiler = shaderc::Compiler::new().unwrap();
+        let cs_spirv = compiler.compile_into_spirv(cs_src, shaderc::ShaderKind::Compute, "absoluteerror.comp", "main", None).unwrap();
+        let cs_module = device.create_shader_module(
+            &wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::util::make_spirv(cs_spirv.as_binary_u8()),
+            }
+        );
+        
+        let pipeline_layout = device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&bind_group_layout_0],
+                push_constant_ranges: &[],
+            }
+        );
+
+        let compute_pipeline = device.create_compute_pipeline(
+            &wgpu::ComputePipelineDescriptor {
+                label: Some("Absolute Error pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &cs_module,
+                entry_point: "main",
+            }
+        );
+
+         Pipeline {
+            output_buffer,
+            bind_group_0,
+            compute_pipeline,
+        }
+    }
+
+    pub fn run(&self, encoder: &mut wgpu::CommandEncoder, _m_size: usize, n_size: usize,) {
+        //Create compute pass
+        let mut compute_pass = encoder.begin_compute_pass(
+            &wgpu::ComputePassDescriptor {
+                label: Some("Absolute Error"),
+            }
+        );
+
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group_0, &[]);
+        //Work groups of X = n_size, Y = 1, Z = 1
+        compute_pass.dispatch(n_size as u32, 1, 1);
+    }
+}
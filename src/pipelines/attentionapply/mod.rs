@@ -0,0 +1,163 @@
+pub struct Pipeline {
+    pub output_buffer: wgpu::Buffer,
+    bind_group_0: wgpu::BindGroup,
+    compute_pipeline: wgpu::ComputePipeline,
+}
+
+impl Pipeline {
+    //Take a t x (t h n) attention matrix and a (d t) x n sequence matrix and mix the steps per head to make a (d t) x n matrix
+    pub fn new<T: bytemuck::Pod>(anchor: &super::Device,
+                                 buffers: (&wgpu::Buffer, // uniform buffer
+                                           &wgpu::Buffer, // t x (t h n) matrix
+                                           &wgpu::Buffer),// (d t) x n matrix
+                                 d_size: usize,
+                                 t_size: usize,
+                                 n_size: usize,) -> Self {
+        let type_size = std::mem::size_of::<T>();
+        let device = &anchor.device;
+        
+        //Create/load buffers
+        
+        let uniform_buffer = buffers.0;
+        //0-0
+        
+        let attention_buffer = buffers.1;
+        //0-1
+        
+        let value_buffer = buffers.2;
+        //0-2
+        
+        let output_buffer = device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Output buffer"),
+                size: (type_size * d_size * t_size * n_size) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }
+        );
+        //0-3
+        
+        //Create bind group(s)
+        let bind_group_layout_0 = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: Some("Attention Apply bind group layout 0"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(0),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage {
+                            read_only: false,
+                        },
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(0),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage {
+                            read_only: false,
+                        },
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(0),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage {
+                            read_only: false,
+                        },
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(0),
+                    },
+                    count: None,
+                },],
+            }
+        );
+        let bind_group_0 = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label:  Some("Attention Apply bind group 0"),
+                layout: &bind_group_layout_0,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: attention_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: value_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: output_buffer.as_entire_binding(),
+                },],
+            }
+        );
+
+        //Create compute pipeline
+        let cs_src = include_str!("shader.comp");
+        let mut compiler = shaderc::Compiler::new().unwrap();
+        let cs_spirv = compiler.compile_into_spirv(cs_src, shaderc::ShaderKind::Compute, "attentionapply.comp", "main", None).unwrap();
+        let cs_module = device.create_shader_module(
+            &wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::util::make_spirv(cs_spirv.as_binary_u8()),
+            }
+        );
+        
+        let pipeline_layout = device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&bind_group_layout_0],
+                push_constant_ranges: &[],
+            }
+        );
+
+        let compute_pipeline = device.create_compute_pipeline(
+            &wgpu::ComputePipelineDescriptor {
+                label: Some("Attention Apply pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &cs_module,
+                entry_point: "main",
+            }
+        );
+
+         Pipeline {
+            output_buffer,
+            bind_group_0,
+            compute_pipeline,
+        }
+    }
+
+    pub fn run(&self, encoder: &mut wgpu::CommandEncoder, d_size: usize, t_size: usize, n_size: usize,) {
+        //Create compute pass
+        let mut compute_pass = encoder.begin_compute_pass(
+            &wgpu::ComputePassDescriptor {
+                label: Some("Attention Apply"),
+            }
+        );
+
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group_0, &[]);
+        //Work groups of X = d_size, Y = t_size * n_size, Z = 1
+        compute_pass.dispatch(d_size as u32, (t_size * n_size) as u32, 1);
+    }
+}
@@ -9,7 +9,9 @@ impl Pipeline {
     pub fn new<T: bytemuck::Pod>(anchor: &super::Device,
                                  buffers: (&wgpu::Buffer, // uniform buffer
                                            &wgpu::Buffer, // m x n matrix
-                                           &wgpu::Buffer),// m x n matrix
+                                           &wgpu::Buffer, // m x n matrix
+                                           &wgpu::Buffer, // m-length vector
+                                           &wgpu::Buffer),// scalar
                                  _m_size: usize,
                                  n_size: usize,) -> Self {
         let type_size = std::mem::size_of::<T>();
@@ -26,6 +28,12 @@ impl Pipeline {
         let ground_buffer = buffers.2;
         //0-2
 
+        let weight_buffer = buffers.3;
+        //0-3
+
+        let smoothing_buffer = buffers.4;
+        //0-4
+
         
         let output_buffer = device.create_buffer(
             &wgpu::BufferDescriptor {
@@ -35,7 +43,7 @@ impl Pipeline {
                 mapped_at_creation: false,
             }
         );
-        //0-3
+        //0-5
         
         //Create bind group(s)
         let bind_group_layout_0 = device.create_bind_group_layout(
@@ -86,6 +94,30 @@ impl Pipeline {
                         min_binding_size: wgpu::BufferSize::new(0),
                     },
                     count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage {
+                            read_only: false,
+                        },
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(0),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage {
+                            read_only: false,
+                        },
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(0),
+                    },
+                    count: None,
                 },],
             }
         );
@@ -107,6 +139,14 @@ impl Pipeline {
                 },
                 wgpu::BindGroupEntry {
                     binding: 3,
+                    resource: weight_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: smoothing_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
                     resource: output_buffer.as_entire_binding(),
                 },],
             }
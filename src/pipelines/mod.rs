@@ -1,28 +1,96 @@
+pub mod absoluteerror;
+pub mod absoluteerrorprime;
 pub mod addvectortobatch;
+pub mod argmax;
+pub mod attentionapply;
+pub mod attentioninputgrad;
+pub mod attentionscores;
+pub mod attentionvaluegrad;
 pub mod batchmax;
 pub mod batchmean;
 pub mod batchnorm;
-pub mod batchnormprime;
+pub mod batchnormgrad;
 pub mod batchtotal;
 pub mod batchvar;
+pub mod binarycrossentropy;
+pub mod binarycrossentropylogits;
+pub mod binarycrossentropylogitsprime;
+pub mod binarycrossentropyprime;
+pub mod concatrows;
+pub mod confusionmatrix;
+pub mod contrastive;
+pub mod contrastiveprime;
 pub mod copymatrix;
 pub mod crossentropy;
 pub mod crossentropyprime;
 pub mod dividebatchbyvector;
+pub mod elementadd;
 pub mod elementmultiply;
 pub mod elementsubtract;
+pub mod elementwise;
+pub mod embeddinggrad;
+pub mod embeddinglookup;
 pub mod expfunct;
+pub mod focal;
+pub mod focalprime;
+pub mod grubackward;
+pub mod gruforward;
+pub mod huber;
+pub mod huberprime;
+pub mod kldivergence;
+pub mod kldivergenceprime;
+pub mod layermean;
+pub mod layernorm;
+pub mod layernormprime;
+pub mod layervar;
 pub mod leakyrelu;
 pub mod leakyreluprime;
+pub mod lerp;
+pub mod logcosh;
+pub mod logcoshprime;
+pub mod logsumexp;
+pub mod lstmbackward;
+pub mod lstmforward;
 pub mod matrixmultiply;
 pub mod multiplybytranspose;
 pub mod multiplytransposewith;
+pub mod padrows;
+pub mod permuteaxes;
+pub mod prelu;
+pub mod preluprime;
+pub mod preluslopegrad;
+pub mod projectiongrad;
+pub mod quantile;
+pub mod quantileprime;
+pub mod recurrentweightgrad;
+pub mod regularisationgrad;
+pub mod regularisationpenalty;
+pub mod rnnbackward;
+pub mod rnnforward;
 pub mod scalarmultiply;
 pub mod scalebatchwithvector;
+pub mod sequencelast;
+pub mod softmaxcrossentropy;
+pub mod softmaxcrossentropyprime;
+pub mod softmaxcrossentropytotal;
+pub mod softmaxfocal;
+pub mod softmaxfocalprime;
+pub mod softmaxfocaltotal;
 pub mod softmaxprime;
+pub mod sparsecrossentropy;
+pub mod sparsecrossentropyprime;
+pub mod sparsesoftmaxcrossentropy;
+pub mod sparsesoftmaxcrossentropyprime;
+pub mod sparsesoftmaxcrossentropytotal;
+pub mod splitrows;
 pub mod squarederror;
 pub mod subtractscalarsfrombatch;
+pub mod temperaturesoftmax;
+pub mod topkhits;
 pub mod totalofbatch;
+pub mod tripletmargin;
+pub mod tripletmarginprime;
+pub mod tripletmining;
 pub mod updatemean;
 pub mod updatesample;
 pub mod updatevar;
@@ -58,3 +126,34 @@ impl Device {
         }
     }
 }
+
+//Uniform buffer of u32 dimensions, shared by pipelines built outside a layer
+pub fn uniform_buffer(anchor: &Device, uniform_data: &[u32]) -> wgpu::Buffer {
+    use wgpu::util::{BufferInitDescriptor, DeviceExt};
+    anchor.device.create_buffer_init(
+        &BufferInitDescriptor {
+            label: Some("Uniform Buffer"),
+            contents: bytemuck::cast_slice(uniform_data),
+            usage: wgpu::BufferUsages::UNIFORM,
+        }
+    )
+}
+
+//Copies size floats into a new storage buffer on the encoder
+pub fn copy_buffer(buffer: &wgpu::Buffer, size: usize, anchor: &Device, encoder: &mut wgpu::CommandEncoder) -> wgpu::Buffer {
+    let type_size = std::mem::size_of::<f32>();
+    let copy = anchor.device.create_buffer(
+        &wgpu::BufferDescriptor {
+            label: Some("Output buffer"),
+            size: (type_size * size) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }
+    );
+    encoder.copy_buffer_to_buffer(
+        buffer, 0,
+        &copy, 0,
+        (type_size * size) as wgpu::BufferAddress,
+    );
+    copy
+}
@@ -0,0 +1,240 @@
+use crate::data::DataSet;
+use crate::network;
+use crate::network::perceptron::Network;
+
+use super::{Trainer, Validation};
+
+//Hooks run by Trainer::fit, the trainer can be changed or stopped from them
+pub trait Callback<D: bytemuck::Pod, L: bytemuck::Pod> {
+    fn on_batch_end(&mut self, _trainer: &mut Trainer<D, L>, _batch: &DataSet<D, L>) {}
+
+    //Runs after validation, if the epoch was validated
+    fn on_epoch_end(&mut self, _trainer: &mut Trainer<D, L>) {}
+
+    //Runs once fit returns, whether it was stopped or ran out of epochs
+    fn on_train_end(&mut self, _trainer: &mut Trainer<D, L>) {}
+
+    //Internal state stored in training checkpoints, restored with load_state when resuming
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_state(&mut self, _state: &[u8]) {}
+}
+
+//Validation value watched by callbacks
+#[derive(Debug, Clone, Copy)]
+pub enum Monitor {
+    Cost,
+    Accuracy,
+    TopKAccuracy,
+    MacroF1,
+}
+
+impl Monitor {
+    //None when the validation has no value for this monitor
+    pub fn value(&self, validation: &Validation) -> Option<f32> {
+        match self {
+            Monitor::Cost => Some(validation.cost),
+            Monitor::Accuracy => validation.evaluation.as_ref().map(|evaluation| evaluation.accuracy()),
+            Monitor::TopKAccuracy => validation.evaluation.as_ref().map(|evaluation| evaluation.top_k_accuracy()),
+            Monitor::MacroF1 => validation.evaluation.as_ref().map(|evaluation| evaluation.macro_f1()),
+        }
+    }
+
+    pub fn higher_is_better(&self) -> bool {
+        !matches!(self, Monitor::Cost)
+    }
+}
+
+//Prints the cost of every batch_frequency-th batch and each validation
+pub struct Logger {
+    pub batch_frequency: usize,
+}
+
+impl<D: bytemuck::Pod, L: bytemuck::Pod> Callback<D, L> for Logger {
+    fn on_batch_end(&mut self, trainer: &mut Trainer<D, L>, batch: &DataSet<D, L>) {
+        if self.batch_frequency > 0 && trainer.batch % self.batch_frequency == 0 {
+            println!("Epoc: {}, Batch: {}, Cost: {}", trainer.epoch, trainer.batch, trainer.batch_cost(batch));
+        }
+    }
+
+    fn on_epoch_end(&mut self, trainer: &mut Trainer<D, L>) {
+        match &trainer.validation {
+            Some(validation) => {
+                match &validation.evaluation {
+                    Some(evaluation) => println!("Epoc: {} done, Validation cost: {}, Accuracy: {}, Top {}: {}, Macro F1: {}",
+                                                 trainer.epoch, validation.cost, evaluation.accuracy(), evaluation.top_k, evaluation.top_k_accuracy(), evaluation.macro_f1()),
+                    None => println!("Epoc: {} done, Validation cost: {}", trainer.epoch, validation.cost),
+                }
+            },
+            None => println!("Epoc: {} done", trainer.epoch),
+        }
+    }
+}
+
+//Saves the network every epoch_frequency epochs
+pub struct Checkpoint {
+    pub filelocation: String,
+    pub epoch_frequency: usize,
+}
+
+impl<D: bytemuck::Pod, L: bytemuck::Pod> Callback<D, L> for Checkpoint {
+    fn on_epoch_end(&mut self, trainer: &mut Trainer<D, L>) {
+        if self.epoch_frequency > 0 && trainer.epoch % self.epoch_frequency == 0 {
+            trainer.save_from_gpu();
+            trainer.network.save_to_file(&self.filelocation);
+        }
+    }
+}
+
+//Where EarlyStopping keeps the weights of the best epoch
+#[derive(Debug, Clone)]
+pub enum BestWeights {
+    Discard,
+    Memory,
+    File(String),
+}
+
+//Stops training once the monitored value hasn't improved by more than min_delta for patience validations
+//When training ends the best weights are restored into the trainer, unless they are discarded
+pub struct EarlyStopping {
+    monitor: Monitor,
+    patience: usize,
+    min_delta: f32,
+    best_weights: BestWeights,
+    best: Option<f32>,
+    best_epoch: usize,
+    wait: usize,
+    //Whether the optimiser has stepped since the best weights were saved
+    stepped_since_best: bool,
+    saved_network: Option<Vec<u8>>,
+}
+
+impl EarlyStopping {
+    pub fn new(monitor: Monitor, patience: usize, min_delta: f32, best_weights: BestWeights) -> Self {
+        assert!(min_delta >= 0.0, "Min delta must not be negative");
+        EarlyStopping {
+            monitor,
+            patience,
+            min_delta,
+            best_weights,
+            best: None,
+            best_epoch: 0,
+            wait: 0,
+            stepped_since_best: false,
+            saved_network: None,
+        }
+    }
+
+    //Value and epoch of the best validation so far
+    pub fn best(&self) -> Option<(f32, usize)> {
+        self.best.map(|best| (best, self.best_epoch))
+    }
+
+    fn save_best<D: bytemuck::Pod, L: bytemuck::Pod>(&mut self, trainer: &mut Trainer<D, L>) {
+        match &self.best_weights {
+            BestWeights::Discard => (),
+            BestWeights::Memory => {
+                trainer.save_from_gpu();
+                self.saved_network = Some(network::format::encode(&trainer.network));
+            },
+            BestWeights::File(filelocation) => {
+                trainer.save_from_gpu();
+                trainer.network.save_to_file(filelocation);
+            },
+        }
+    }
+}
+
+impl<D: bytemuck::Pod, L: bytemuck::Pod> Callback<D, L> for EarlyStopping {
+    fn on_batch_end(&mut self, _trainer: &mut Trainer<D, L>, _batch: &DataSet<D, L>) {
+        self.stepped_since_best = true;
+    }
+
+    fn on_epoch_end(&mut self, trainer: &mut Trainer<D, L>) {
+        //Epochs without a validation neither improve nor use up patience
+        let value = match trainer.validation.as_ref().and_then(|validation| self.monitor.value(validation)) {
+            Some(value) => value,
+            None => return,
+        };
+        let improved = match self.best {
+            Some(best) if self.monitor.higher_is_better() => value > best + self.min_delta,
+            Some(best) => value < best - self.min_delta,
+            None => true,
+        };
+        if improved {
+            self.best = Some(value);
+            self.best_epoch = trainer.epoch;
+            self.wait = 0;
+            self.stepped_since_best = false;
+            self.save_best(trainer);
+        } else {
+            self.wait += 1;
+            if self.wait >= self.patience {
+                trainer.stop();
+            }
+        }
+    }
+
+    fn on_train_end(&mut self, trainer: &mut Trainer<D, L>) {
+        //Nothing to restore if the weights haven't changed since the best validation
+        if self.best.is_none() || !self.stepped_since_best {
+            return;
+        }
+        match &self.best_weights {
+            BestWeights::Discard => (),
+            BestWeights::Memory => {
+                let network = network::format::decode(self.saved_network.as_ref().unwrap(), "best weights");
+                trainer.restore(network);
+            },
+            BestWeights::File(filelocation) => {
+                let network = Network::load_from_file(filelocation);
+                trainer.restore(network);
+            },
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        bincode::serialize(&(self.best, self.best_epoch, self.wait, self.stepped_since_best, &self.saved_network)).unwrap()
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let (best, best_epoch, wait, stepped_since_best, saved_network) = bincode::deserialize(state).unwrap();
+        self.best = best;
+        self.best_epoch = best_epoch;
+        self.wait = wait;
+        self.stepped_since_best = stepped_since_best;
+        self.saved_network = saved_network;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Schedule {
+    //Multiply by factor every step_size epochs
+    Step(usize, f32),
+    //Multiply by factor every epoch
+    Exponential(f32),
+}
+
+//Sets the learning rate from the number of completed epochs
+pub struct LearningRateScheduler {
+    pub initial_learning_rate: f32,
+    pub schedule: Schedule,
+}
+
+impl LearningRateScheduler {
+    pub fn learning_rate(&self, epoch: usize) -> f32 {
+        match self.schedule {
+            Schedule::Step(step_size, factor) => self.initial_learning_rate * factor.powi((epoch / step_size.max(1)) as i32),
+            Schedule::Exponential(factor) => self.initial_learning_rate * factor.powi(epoch as i32),
+        }
+    }
+}
+
+impl<D: bytemuck::Pod, L: bytemuck::Pod> Callback<D, L> for LearningRateScheduler {
+    fn on_epoch_end(&mut self, trainer: &mut Trainer<D, L>) {
+        let learning_rate = self.learning_rate(trainer.epoch);
+        trainer.optimiser.set_learning_rate(learning_rate);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::pipelines;
+use crate::data::DataSet;
+use crate::network;
+use crate::optimisers;
+
+use serde::{Serialize, Deserialize};
+use std::path::Path;
+
+use super::{Callback, Trainer, TrainerConfig};
+
+//Where and how often Trainer::fit writes training checkpoints
+#[derive(Debug, Clone)]
+pub struct CheckpointConfig {
+    pub directory: String,
+    //Write after every epoch_frequency epochs and every batch_frequency batches, 0 disables either
+    pub epoch_frequency: usize,
+    pub batch_frequency: usize,
+    //Only the newest keep_last checkpoints are kept, 0 keeps all of them
+    pub keep_last: usize,
+}
+
+//Everything needed to continue an interrupted run exactly where it stopped
+#[derive(Serialize, Deserialize)]
+pub struct TrainingCheckpoint {
+    //Network with its weights, encoded with network::format
+    pub network: Vec<u8>,
+    pub optimiser: optimisers::Stochasticgradientdescent,
+    pub epoch: usize,
+    pub batch: usize,
+    //Epoch shuffles are seeded from this, so a resumed epoch replays the same batches
+    pub seed: u64,
+    //Callback::save_state of every callback, in order
+    pub callback_states: Vec<Vec<u8>>,
+}
+
+impl TrainingCheckpoint {
+    pub fn save_to_file(&self, filelocation: &str) {
+        network::format::save_to_file(self, filelocation);
+    }
+
+    pub fn load_from_file(filelocation: &str) -> Self {
+        network::format::load_from_file(filelocation)
+    }
+}
+
+const CHECKPOINT_PREFIX: &str = "checkpoint-";
+const CHECKPOINT_EXTENSION: &str = ".bin";
+
+//Zero padded so the file names sort in training order
+fn checkpoint_location(directory: &str, epoch: usize, batch: usize) -> String {
+    Path::new(directory).join(format!("{}{:06}-{:08}{}", CHECKPOINT_PREFIX, epoch, batch, CHECKPOINT_EXTENSION)).to_string_lossy().into_owned()
+}
+
+//Checkpoint files in directory, oldest first
+pub fn list_checkpoints(directory: &str) -> Vec<String> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut names: Vec<String> = entries.filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with(CHECKPOINT_PREFIX) && name.ends_with(CHECKPOINT_EXTENSION))
+        .collect();
+    names.sort();
+    names.into_iter().map(|name| Path::new(directory).join(name).to_string_lossy().into_owned()).collect()
+}
+
+pub fn latest_checkpoint(directory: &str) -> Option<String> {
+    list_checkpoints(directory).pop()
+}
+
+//Removes all but the newest keep_last checkpoints, 0 keeps all of them
+fn prune_checkpoints(directory: &str, keep_last: usize) {
+    if keep_last == 0 {
+        return;
+    }
+    let checkpoints = list_checkpoints(directory);
+    let excess = checkpoints.len().saturating_sub(keep_last);
+    for filelocation in checkpoints[..excess].iter() {
+        if let Err(e) = std::fs::remove_file(filelocation) {
+            eprintln!("Error: could not remove checkpoint {}: {}", filelocation, e);
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl<D: bytemuck::Pod, L: bytemuck::Pod> Trainer<D, L> {
+    //Writes a checkpoint of the current position to the configured directory and applies the retention policy
+    pub fn save_checkpoint(&mut self, callbacks: &Vec<Box<dyn Callback<D, L>>>) {
+        let checkpoint_config = match &self.config.checkpoint {
+            Some(checkpoint_config) => checkpoint_config.clone(),
+            None => return,
+        };
+        std::fs::create_dir_all(&checkpoint_config.directory).unwrap();
+
+        self.save_from_gpu();
+        let checkpoint = TrainingCheckpoint {
+            network: network::format::encode(&self.network),
+            optimiser: self.optimiser.clone(),
+            epoch: self.epoch,
+            batch: self.batch,
+            seed: self.config.seed,
+            callback_states: callbacks.iter().map(|callback| callback.save_state()).collect(),
+        };
+        checkpoint.save_to_file(&checkpoint_location(&checkpoint_config.directory, self.epoch, self.batch));
+
+        prune_checkpoints(&checkpoint_config.directory, checkpoint_config.keep_last);
+    }
+
+    //Rebuilds a trainer from a checkpoint file, fit then continues from the saved epoch and batch
+    //Callbacks must be given in the same order as when the checkpoint was written
+    //The seed stored in the checkpoint replaces config.seed
+    pub fn resume(filelocation: &str,
+                  training_data: DataSet<D, L>,
+                  validation_data: Option<DataSet<D, L>>,
+                  mut config: TrainerConfig,
+                  anchor: pipelines::Device,
+                  callbacks: &mut Vec<Box<dyn Callback<D, L>>>,) -> Self {
+        let checkpoint = TrainingCheckpoint::load_from_file(filelocation);
+        assert_eq!(checkpoint.callback_states.len(), callbacks.len(), "Checkpoint was written with a different number of callbacks");
+        for (callback, state) in callbacks.iter_mut().zip(checkpoint.callback_states.iter()) {
+            callback.load_state(state);
+        }
+
+        config.seed = checkpoint.seed;
+        let network = network::format::decode(&checkpoint.network, filelocation);
+        let mut trainer = Trainer::new(network, checkpoint.optimiser, training_data, validation_data, config, anchor);
+        trainer.epoch = checkpoint.epoch;
+        trainer.batch = checkpoint.batch;
+        trainer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Fresh directory per test so tests running in parallel don't see each other's files
+    fn test_directory(name: &str) -> String {
+        let directory = std::env::temp_dir().join(format!("rust_perceptron_checkpoints_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory.to_string_lossy().into_owned()
+    }
+
+    fn touch(directory: &str, epoch: usize, batch: usize) {
+        std::fs::write(checkpoint_location(directory, epoch, batch), []).unwrap();
+    }
+
+    fn positions(directory: &str) -> Vec<String> {
+        list_checkpoints(directory).iter().map(|location| Path::new(location).file_name().unwrap().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn checkpoint_names_are_zero_padded() {
+        let location = checkpoint_location("runs", 3, 42);
+        assert_eq!(location, Path::new("runs").join("checkpoint-000003-00000042.bin").to_string_lossy());
+    }
+
+    #[test]
+    fn checkpoints_list_in_training_order_across_epochs_and_batches() {
+        let directory = test_directory("order");
+        //Written out of order, and with counts that would sort wrongly without padding
+        for &(epoch, batch) in [(10, 0), (2, 100), (2, 9), (9, 5), (0, 1000)].iter() {
+            touch(&directory, epoch, batch);
+        }
+        //Other files in the directory are ignored
+        std::fs::write(Path::new(&directory).join("notes.txt"), []).unwrap();
+        std::fs::write(Path::new(&directory).join("checkpoint-final.json"), []).unwrap();
+
+        assert_eq!(positions(&directory), vec![
+            "checkpoint-000000-00001000.bin",
+            "checkpoint-000002-00000009.bin",
+            "checkpoint-000002-00000100.bin",
+            "checkpoint-000009-00000005.bin",
+            "checkpoint-000010-00000000.bin",
+        ]);
+        assert_eq!(latest_checkpoint(&directory), Some(checkpoint_location(&directory, 10, 0)));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn missing_directory_has_no_checkpoints() {
+        let directory = std::env::temp_dir().join(format!("rust_perceptron_checkpoints_missing_{}", std::process::id()));
+        assert!(list_checkpoints(&directory.to_string_lossy()).is_empty());
+        assert_eq!(latest_checkpoint(&directory.to_string_lossy()), None);
+    }
+
+    #[test]
+    fn pruning_keeps_the_newest_checkpoints() {
+        let directory = test_directory("prune");
+        for &(epoch, batch) in [(1, 50), (0, 100), (1, 0), (2, 0)].iter() {
+            touch(&directory, epoch, batch);
+        }
+
+        prune_checkpoints(&directory, 2);
+        assert_eq!(positions(&directory), vec!["checkpoint-000001-00000050.bin", "checkpoint-000002-00000000.bin"]);
+
+        //Fewer checkpoints than keep_last are all kept
+        prune_checkpoints(&directory, 5);
+        assert_eq!(positions(&directory).len(), 2);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn pruning_with_zero_keeps_everything() {
+        let directory = test_directory("keep_all");
+        for epoch in 0..4 {
+            touch(&directory, epoch, 0);
+        }
+
+        prune_checkpoints(&directory, 0);
+        assert_eq!(positions(&directory).len(), 4);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
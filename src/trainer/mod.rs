@@ -0,0 +1,173 @@
+use crate::pipelines;
+use crate::data::DataSet;
+use crate::network::perceptron::Network;
+use crate::network::metrics;
+use crate::optimisers;
+
+use rand::{rngs::StdRng, SeedableRng};
+use std::any::Any;
+
+pub mod callbacks;
+pub mod checkpoint;
+
+pub use callbacks::Callback;
+pub use checkpoint::CheckpointConfig;
+
+#[derive(Debug, Clone)]
+pub struct TrainerConfig {
+    pub epochs: usize,
+    pub batch_size: usize,
+    //Validate after every validation_frequency epochs, 0 never validates
+    pub validation_frequency: usize,
+    //k for the top k accuracy of classification tasks
+    pub top_k: usize,
+    //Each epoch is shuffled with a generator seeded from seed and the epoch number
+    pub seed: u64,
+    pub checkpoint: Option<CheckpointConfig>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Validation {
+    //Mean over the whole validation set
+    pub cost: f32,
+    //Only for u32 class labels
+    pub evaluation: Option<metrics::Evaluation>,
+}
+
+pub struct Trainer<D: bytemuck::Pod, L: bytemuck::Pod> {
+    pub network: Network,
+    pub network_data: Vec<Vec<wgpu::Buffer>>,
+    pub optimiser: optimisers::Stochasticgradientdescent,
+    pub training_data: DataSet<D, L>,
+    pub validation_data: Option<DataSet<D, L>>,
+    pub config: TrainerConfig,
+    pub anchor: pipelines::Device,
+    //Completed epochs, and batches completed in the current epoch
+    pub epoch: usize,
+    pub batch: usize,
+    //Result of the validation at the end of the current epoch, None when it was not validated
+    pub validation: Option<Validation>,
+    stop_requested: bool,
+}
+
+#[allow(dead_code)]
+impl<D: bytemuck::Pod, L: bytemuck::Pod> Trainer<D, L> {
+    pub fn new(network: Network,
+               optimiser: optimisers::Stochasticgradientdescent,
+               training_data: DataSet<D, L>,
+               validation_data: Option<DataSet<D, L>>,
+               config: TrainerConfig,
+               anchor: pipelines::Device,) -> Self {
+        let network_data = network.load_to_gpu(&anchor);
+        Trainer {
+            network,
+            network_data,
+            optimiser,
+            training_data,
+            validation_data,
+            config,
+            anchor,
+            epoch: 0,
+            batch: 0,
+            validation: None,
+            stop_requested: false,
+        }
+    }
+
+    //Ends training after the current batch
+    pub fn stop(&mut self) {
+        self.stop_requested = true;
+    }
+
+    //Runs until config.epochs epochs are complete or a callback stops it
+    //A resumed trainer skips the batches of the current epoch that were already done
+    pub fn fit(&mut self, callbacks: &mut Vec<Box<dyn Callback<D, L>>>) {
+        self.stop_requested = false;
+        let network_topology = self.network.get_topology();
+        let (checkpoint_epochs, checkpoint_batches) = match &self.config.checkpoint {
+            Some(checkpoint_config) => (checkpoint_config.epoch_frequency, checkpoint_config.batch_frequency),
+            None => (0, 0),
+        };
+
+        while self.epoch < self.config.epochs && !self.stop_requested {
+            //Break epoc into batches
+            let mut rng = StdRng::seed_from_u64(self.config.seed.wrapping_add(self.epoch as u64));
+            for batch in self.training_data.generate_epoc_with_rng(self.config.batch_size, &mut rng).into_iter().skip(self.batch) {
+                //Step optimization
+                let network_grads = self.network.backprop::<D, L>(&batch.get_data(), &batch.get_labels(), &mut self.network_data, &self.anchor, batch.get_size());
+                self.optimiser.step(&mut self.network_data, &network_grads, &self.anchor, &network_topology);
+                self.batch += 1;
+
+                for callback in callbacks.iter_mut() {
+                    callback.on_batch_end(self, &batch);
+                }
+                if checkpoint_batches > 0 && self.batch % checkpoint_batches == 0 {
+                    self.save_checkpoint(callbacks);
+                }
+                if self.stop_requested {
+                    break;
+                }
+            }
+            if self.stop_requested {
+                break;
+            }
+            //End epoc
+            self.epoch += 1;
+            self.batch = 0;
+
+            //Unvalidated epochs clear the last validation so callbacks don't count it twice
+            self.validation = match self.config.validation_frequency > 0 && self.epoch % self.config.validation_frequency == 0 {
+                true => self.validate(),
+                false => None,
+            };
+            for callback in callbacks.iter_mut() {
+                callback.on_epoch_end(self);
+            }
+            if checkpoint_epochs > 0 && self.epoch % checkpoint_epochs == 0 {
+                self.save_checkpoint(callbacks);
+            }
+        }
+
+        for callback in callbacks.iter_mut() {
+            callback.on_train_end(self);
+        }
+    }
+
+    //Streams the whole validation set through the network, None without validation data
+    pub fn validate(&self) -> Option<Validation> {
+        let validation_data = self.validation_data.as_ref()?;
+        let mut total_cost = 0.0;
+        for batch in validation_data.batches(self.config.batch_size).into_iter() {
+            let batch_size = batch.get_size();
+            let cost = self.network.cost::<D, L>(&batch.get_data(), &batch.get_labels(), &self.network_data, &self.anchor, batch_size, true);
+            total_cost += metrics::read_buffer(&cost, &self.anchor, 1).unwrap()[0] * batch_size as f32;
+        }
+
+        //Classification metrics need class index labels
+        let evaluation = (validation_data as &dyn Any).downcast_ref::<DataSet<D, u32>>().map(|class_data| {
+            metrics::evaluate(&self.network, &self.network_data, class_data, &self.anchor, self.config.batch_size, self.config.top_k)
+        });
+
+        Some(Validation {
+            cost: total_cost / validation_data.get_size() as f32,
+            evaluation,
+        })
+    }
+
+    //Mean cost of a single batch
+    pub fn batch_cost(&self, batch: &DataSet<D, L>) -> f32 {
+        let cost = self.network.cost::<D, L>(&batch.get_data(), &batch.get_labels(), &self.network_data, &self.anchor, batch.get_size(), true);
+        metrics::read_buffer(&cost, &self.anchor, 1).unwrap()[0]
+    }
+
+    //Replaces the network and its weights on the gpu
+    pub fn restore(&mut self, network: Network) {
+        self.network_data = network.load_to_gpu(&self.anchor);
+        self.network = network;
+    }
+
+    //Copies the weights on the gpu into the network
+    pub fn save_from_gpu(&mut self) {
+        self.network.save_from_gpu(&self.anchor, &self.network_data);
+    }
+}